@@ -1,12 +1,19 @@
 use {
     crate::{
+        cli::Command,
         cmd::Cmd,
-        data::{Entry, Topic},
+        data::{Entry, Id, Topic, assign_ids},
+        dir_store,
+        formats::{ExportFormat, ExportScope, ImportFormat},
+        history::History,
+        ipc::{self, IncomingCommand, IncomingCommands, Reply},
+        merge::merge_topics,
         tree,
     },
+    chrono::NaiveDateTime,
     eframe::{
         Frame,
-        egui::{self, FontDefinitions, FontFamily},
+        egui::{self, FontDefinitions, FontFamily, KeyboardShortcut},
     },
     egui_commonmark::CommonMarkCache,
     egui_file_dialog::FileDialog,
@@ -17,7 +24,10 @@ use {
         collections::{BTreeMap, HashMap},
         error::Error,
         fs::File,
+        hash::{Hash as _, Hasher as _},
+        io::{BufRead, BufReader, Write},
         path::{Path, PathBuf},
+        time::{Duration, Instant, SystemTime},
     },
 };
 
@@ -27,26 +37,276 @@ pub struct TodoAppPersistent {
     pub topics: Vec<Topic>,
     #[serde(default)]
     pub stored_font_data: Option<StoredFontData>,
+    #[serde(default)]
+    pub settings: Settings,
+}
+
+/// Identifies setodo data files.
+///
+/// Files from before the header existed start directly with zstd compressed data.
+const MAGIC: &[u8; 8] = b"SETODO\0\0";
+
+/// Version of the data file format written by this version of setodo
+const FORMAT_VERSION: u32 = 2;
+
+/// Upgrades from each older format version to the next one.
+///
+/// `MIGRATIONS[n]` upgrades from version `n` to `n + 1`.
+/// They run after decoding, so layout changes that serde can't handle by itself (e.g. with
+/// `#[serde(default)]`) need to be decoded into a legacy type first.
+const MIGRATIONS: [fn(&mut TodoAppPersistent); FORMAT_VERSION as usize] = [
+    // Version 0 had no header, and topics and entries had no ids
+    |per| assign_ids(&mut per.topics),
+    // Version 1 had no subtasks and no `auto_complete_parents` setting. Both default, so
+    // there's nothing to do, but older versions of setodo must not load the new layout.
+    |_| {},
+];
+
+/// Read the header of a data file, and return its format version.
+///
+/// Headerless (legacy) files are version 0, and nothing is consumed from them.
+pub fn read_header(reader: &mut impl BufRead) -> std::io::Result<u32> {
+    if !reader.fill_buf()?.starts_with(MAGIC) {
+        return Ok(0);
+    }
+    reader.consume(MAGIC.len());
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    Ok(u32::from_le_bytes(version))
+}
+
+/// User preferences that are saved along with the data
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// How many previous versions of the data file to keep as backups
+    pub backup_count: u32,
+    /// Save unsaved changes after this many seconds. 0 disables autosave.
+    pub autosave_interval_secs: u32,
+    /// Save unsaved changes when the window loses focus
+    pub save_on_focus_loss: bool,
+    /// Write attachments next to exported documents
+    pub export_attachments: bool,
+    /// Mark tasks as done when all of their subtasks are
+    pub auto_complete_parents: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            backup_count: 3,
+            autosave_interval_secs: 120,
+            save_on_focus_loss: false,
+            export_attachments: false,
+            auto_complete_parents: false,
+        }
+    }
 }
 
 impl TodoAppPersistent {
-    fn load(data_file_path: &Path) -> Result<Self, Box<dyn Error>> {
+    pub fn load(data_file_path: &Path) -> Result<Self, Box<dyn Error>> {
         if !data_file_path.exists() {
             eprintln!("'{}' doesn't exist. Creating.", data_file_path.display());
             Self::default().save_to_file(data_file_path)?;
         }
-        let file = File::open(data_file_path)?;
-        let dec = zstd::stream::read::Decoder::new(file)?;
-        Ok(rmp_serde::from_read(dec)?)
+        Self::read_from_file(data_file_path)
+    }
+
+    /// Read the data file, or directory store, at `path`
+    pub fn read_from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if dir_store::is_store(path) {
+            return dir_store::read(path);
+        }
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Read data in the data file format, upgrading it to the current layout if necessary
+    pub fn read_from(mut reader: impl BufRead) -> Result<Self, Box<dyn Error>> {
+        let version = read_header(&mut reader)?;
+        if version > FORMAT_VERSION {
+            return Err(format!(
+                "The data file was written by a newer version of setodo \
+                 (format version {version}, this version supports up to {FORMAT_VERSION}).\n\
+                 Refusing to load it, so it doesn't get damaged."
+            )
+            .into());
+        }
+        let dec = zstd::stream::read::Decoder::with_buffer(reader)?;
+        let mut per: Self = rmp_serde::from_read(dec)?;
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(&mut per);
+        }
+        Ok(per)
+    }
+
+    /// Serialize as RON text, which can be read, diffed and fixed by hand.
+    ///
+    /// Unlike the data file, the text has no version header. It always has the current layout.
+    pub fn to_text(&self) -> Result<String, Box<dyn Error>> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    /// Read what [`Self::to_text`] wrote, possibly after being edited by hand
+    pub fn from_text(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut per: Self = ron::from_str(text)?;
+        // Hand written topics and entries don't have ids
+        assign_ids(&mut per.topics);
+        Ok(per)
     }
 
-    fn save_to_file(&self, path: &Path) -> Result<(), Box<dyn Error>> {
-        let file = File::create(path)?;
-        let mut enc = zstd::stream::write::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+    /// Write data in the data file format
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<W, Box<dyn Error>> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        let mut enc = zstd::stream::write::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?;
         self.serialize(&mut Serializer::new(&mut enc))?;
-        enc.finish()?;
+        Ok(enc.finish()?)
+    }
+
+    /// Save to `path`, without ever leaving a partially written file behind.
+    ///
+    /// The data is written to a temporary file first, which then replaces the original.
+    /// The previous version of the file is kept as a backup, at most once an hour.
+    ///
+    /// Directory stores are written file by file instead (see [`dir_store::write`]), without
+    /// backups. They're meant to be version controlled.
+    pub fn save_to_file(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if dir_store::is_store(path) {
+            return dir_store::write(self, path);
+        }
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let file = self.write_to(File::create(&tmp_path)?)?;
+        file.sync_all()?;
+        rotate_backups(path, self.settings.backup_count)?;
+        std::fs::rename(&tmp_path, path)?;
+        // Make sure the rename itself is persisted
+        #[cfg(unix)]
+        if let Some(dir) = path.parent()
+            && let Ok(dir) = File::open(if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            })
+        {
+            dir.sync_all()?;
+        }
         Ok(())
     }
+
+    /// Index path of the topic with the given id
+    pub fn topic_path(&self, id: Id) -> Option<Vec<usize>> {
+        tree::find_path(&self.topics, |topic| topic.id == id)
+    }
+
+    /// Id of the currently selected topic, if any
+    pub fn selected_topic_id(&self) -> Option<Id> {
+        tree::get(&self.topics, &self.topic_sel).map(|topic| topic.id)
+    }
+
+    /// Select the topic with the given id, wherever it currently is in the tree.
+    ///
+    /// Selects the root if `id` is `None` or the topic doesn't exist.
+    pub fn select_topic(&mut self, id: Option<Id>) {
+        self.topic_sel = id.and_then(|id| self.topic_path(id)).unwrap_or_default();
+    }
+
+    /// Remove the topic with the given id.
+    ///
+    /// If the selected topic was removed along with it, its parent is selected instead.
+    pub fn remove_topic(&mut self, id: Id) -> Option<Topic> {
+        let path = self.topic_path(id)?;
+        let sel_id = self.selected_topic_id();
+        let topic = tree::remove(&mut self.topics, &path)?;
+        if self.topic_sel.starts_with(&path) {
+            self.topic_sel = path[..path.len() - 1].to_vec();
+        } else {
+            self.select_topic(sel_id);
+        }
+        Some(topic)
+    }
+
+    /// Move the topic `src` into `dst` (or the root if `None`), keeping the selection intact
+    pub fn move_topic_into(&mut self, src: Id, dst: Option<Id>) {
+        let Some(src_path) = self.topic_path(src) else {
+            return;
+        };
+        let dst_path = match dst {
+            Some(dst) => match self.topic_path(dst) {
+                Some(path) => path,
+                None => return,
+            },
+            None => Vec::new(),
+        };
+        let sel_id = self.selected_topic_id();
+        tree::move_(&mut self.topics, &src_path, &dst_path);
+        self.select_topic(sel_id);
+    }
+
+    /// Move the topic `src` before, after or into `dst`, keeping the selection intact.
+    ///
+    /// Does nothing if `dst` is `src` itself or inside it.
+    pub fn move_topic(&mut self, src: Id, dst: Id, placement: Placement) {
+        let (Some(src_path), Some(dst_path)) = (self.topic_path(src), self.topic_path(dst)) else {
+            return;
+        };
+        if !tree::move_precondition(&src_path, &dst_path) {
+            return;
+        }
+        let sel_id = self.selected_topic_id();
+        let (&idx, parent_path) = dst_path.split_last().unwrap();
+        match placement {
+            Placement::Before => tree::move_to(&mut self.topics, &src_path, parent_path, idx),
+            Placement::After => tree::move_to(&mut self.topics, &src_path, parent_path, idx + 1),
+            Placement::Into => tree::move_(&mut self.topics, &src_path, &dst_path),
+        }
+        self.select_topic(sel_id);
+    }
+
+    /// Move the entry with the id `entry` to the end of the topic `topic`
+    pub fn move_entry_to_topic(&mut self, entry: Id, topic: Id) {
+        let src_path = tree::find_path(&self.topics, |t| t.entry_pos(entry).is_some());
+        let (Some(src_path), Some(dst_path)) = (src_path, self.topic_path(topic)) else {
+            return;
+        };
+        if src_path == dst_path {
+            return;
+        }
+        if let Some(entry) = tree::get_mut(&mut self.topics, &src_path)
+            .and_then(|src_topic| src_topic.take_entry(entry))
+            && move_task_into_topic(&mut self.topics, entry, &dst_path).is_err()
+        {
+            eprintln!("Failed to move entry into topic");
+        }
+    }
+
+    /// Select the entry with the given id, along with the topic that contains it
+    pub fn goto_entry(&mut self, id: Id) -> bool {
+        let Some(path) = tree::find_path(&self.topics, |topic| topic.entry_pos(id).is_some())
+        else {
+            return false;
+        };
+        if let Some(topic) = tree::get_mut(&mut self.topics, &path)
+            && let Some(pos) = topic.entry_pos(id)
+        {
+            topic.select_entry(pos);
+        }
+        self.topic_sel = path;
+        true
+    }
+}
+
+/// Where to put a topic relative to another one
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Placement {
+    Before,
+    After,
+    /// As the last child
+    Into,
 }
 
 pub struct TodoApp {
@@ -82,16 +342,117 @@ pub struct TodoAppTemp {
     pub cmd: Vec<Cmd>,
     pub modal: Option<ModalPayload>,
     pub confirm_action: Option<ConfirmAction>,
+    pub history: History,
+    pub autosave: AutosaveState,
+    /// The version of the data file we last loaded or saved. `None` if it didn't exist.
+    pub file_stamp: Option<FileStamp>,
+    /// Commands sent by other setodo processes
+    pub ipc_commands: Option<IncomingCommands>,
+    /// Data files used recently, most recent first
+    pub recent_files: Vec<PathBuf>,
+    /// What the path picked in the file dialog is for, unless it's attachments
+    pub file_op: Option<FileOp>,
+}
+
+#[derive(Clone, Copy)]
+pub enum FileOp {
+    DataFile(DataFileOp),
+    Export {
+        format: ExportFormat,
+        scope: ExportScope,
+    },
+    Import(ImportFormat),
+    /// Write everything as text
+    DumpText,
+    /// Replace everything with a text dump
+    LoadText,
+}
+
+#[derive(Clone, Copy)]
+pub enum DataFileOp {
+    /// Switch to an existing data file
+    Open,
+    /// Create an empty data file, and switch to it
+    New,
+    /// Save the current data to a new file, and continue with that one
+    SaveAs,
+    /// Save the current data to a new directory store, and continue with that one
+    SaveAsDirectory,
+}
+
+/// Identifies a version of a file on disk
+pub struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+    hash: u64,
+}
+
+impl FileStamp {
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let meta = std::fs::metadata(path)?;
+        Ok(Self {
+            modified: meta.modified()?,
+            len: meta.len(),
+            hash: hash_file(path)?,
+        })
+    }
+    /// Whether the file at `path` is different from the version this stamp was taken of
+    fn differs_from(&self, path: &Path) -> std::io::Result<bool> {
+        let meta = std::fs::metadata(path)?;
+        // The modification time of a directory doesn't change when the files in it do
+        if meta.is_file() && meta.modified()? == self.modified && meta.len() == self.len {
+            return Ok(false);
+        }
+        // Only touched, or copied back and forth by a sync tool?
+        Ok(hash_file(path)? != self.hash)
+    }
+}
+
+fn hash_file(path: &Path) -> std::io::Result<u64> {
+    let mut hasher = std::hash::DefaultHasher::new();
+    if dir_store::is_store(path) {
+        dir_store::hash(path, &mut hasher)?;
+    } else {
+        std::fs::read(path)?.hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+pub struct AutosaveState {
+    /// When the data was last saved (or loaded)
+    pub last_save: Instant,
+    /// Whether the window had focus last frame
+    pub was_focused: bool,
+}
+
+impl AutosaveState {
+    fn new() -> Self {
+        Self {
+            last_save: Instant::now(),
+            was_focused: true,
+        }
+    }
 }
 
 /// Actions that need to be confirmed before executed
 #[derive(Clone, Copy)]
 pub enum ConfirmAction {
     ClearTopicEntries,
+    /// Replace the topics with the ones from the backup made at this time
+    RestoreBackup(NaiveDateTime),
 }
 
 pub enum ModalPayload {
     ErrorMsg(String),
+    InfoMsg(String),
+    /// The data file changed on disk, and we were about to overwrite it
+    ExternalModification,
+}
+
+enum ExternalModificationChoice {
+    Reload,
+    Overwrite,
+    Merge,
 }
 
 #[derive(Default)]
@@ -106,7 +467,7 @@ impl ActionFlags {
 }
 
 impl TodoAppTemp {
-    fn new(data_file_path: PathBuf) -> Self {
+    fn new(data_file_path: PathBuf, per: &TodoAppPersistent) -> Self {
         Self {
             state: UiState::Normal,
             font_defs_ui: FontCfgUi::default(),
@@ -117,12 +478,18 @@ impl TodoAppTemp {
             find_string: String::new(),
             esc_was_used: false,
             per_dirty: false,
+            file_stamp: FileStamp::read(&data_file_path).ok(),
+            ipc_commands: None,
+            recent_files: load_recent_files(),
+            file_op: None,
             data_file_path,
             file_dialog: FileDialog::new(),
             action_flags: ActionFlags::default(),
             cmd: Vec::new(),
             modal: None,
             confirm_action: None,
+            history: History::new(per),
+            autosave: AutosaveState::new(),
         }
     }
 }
@@ -131,61 +498,308 @@ pub enum UiState {
     Normal,
     AddSubtopic {
         name: String,
-        parent_idx: Vec<usize>,
+        /// `None` means adding a top level topic
+        parent: Option<Id>,
     },
     AddTask(String),
-    MoveTopicInto {
-        src_idx: Vec<usize>,
+    MoveTopic {
+        src: Id,
+        placement: Placement,
     },
-    MoveTaskIntoTopic(Entry),
+    /// Waiting for a topic to be clicked, to move the entries with these ids into
+    MoveTaskIntoTopic(Vec<Id>),
     RenameTopic {
-        idx: Vec<usize>,
+        id: Id,
     },
     RenameTask {
-        topic_idx: Vec<usize>,
-        task_idx: usize,
+        topic_id: Id,
+        task_id: Id,
     },
     FontCfg,
     EditTopicDesc,
+    Agenda,
+    Preferences,
 }
 
 impl UiState {
-    pub fn add_subtopic(parent_idx: Vec<usize>) -> Self {
+    pub fn add_subtopic(parent: Option<Id>) -> Self {
         Self::AddSubtopic {
             name: String::default(),
-            parent_idx,
+            parent,
         }
     }
     pub fn add_task() -> Self {
         Self::AddTask(String::default())
     }
-    pub const fn move_topic_into(src_idx: Vec<usize>) -> Self {
-        Self::MoveTopicInto { src_idx }
+    pub const fn move_topic(src: Id) -> Self {
+        Self::MoveTopic {
+            src,
+            placement: Placement::Into,
+        }
     }
 }
 
+/// Backups are made at most this often, so that autosaves don't push out all the older ones
+const BACKUP_INTERVAL_SECS: i64 = 60 * 60;
+/// Format of the time in backup file names. Sorts in chronological order.
+const BACKUP_TIME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// Path of the backup of the data file at `path` made at `time`
+pub fn backup_path(path: &Path, time: NaiveDateTime) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".bak.{}", time.format(BACKUP_TIME_FORMAT)));
+    backup.into()
+}
+
+/// Times of the existing backups of the data file at `path`, from most to least recent
+pub fn existing_backups(path: &Path) -> Vec<NaiveDateTime> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let prefix = format!("{}.bak.", name.to_string_lossy());
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut times: Vec<_> = read_dir
+        .filter_map(|dir_entry| {
+            let file_name = dir_entry.ok()?.file_name();
+            let time = file_name.to_str()?.strip_prefix(&prefix)?;
+            NaiveDateTime::parse_from_str(time, BACKUP_TIME_FORMAT).ok()
+        })
+        .collect();
+    times.sort_unstable_by(|a, b| b.cmp(a));
+    times
+}
+
+/// Copy `path` into a new backup, unless the last one is recent enough.
+///
+/// Only the `count` most recent backups are kept, the rest are removed.
+fn rotate_backups(path: &Path, count: u32) -> std::io::Result<()> {
+    let mut backups = existing_backups(path);
+    let now = chrono::Local::now().naive_local();
+    let due = backups
+        .first()
+        .is_none_or(|last| (now - *last).num_seconds() >= BACKUP_INTERVAL_SECS);
+    if count > 0 && due && path.exists() {
+        std::fs::copy(path, backup_path(path, now))?;
+        backups.insert(0, now);
+    }
+    for time in backups.into_iter().skip(count as usize) {
+        std::fs::remove_file(backup_path(path, time))?;
+    }
+    Ok(())
+}
+
 pub fn default_data_file_path() -> PathBuf {
     dirs_sys::home_dir().unwrap().join(".setodo.dat")
 }
 
+const MAX_RECENT_FILES: usize = 10;
+
+fn recent_files_path() -> PathBuf {
+    dirs_sys::home_dir().unwrap().join(".setodo.recent")
+}
+
+fn load_recent_files() -> Vec<PathBuf> {
+    std::fs::read_to_string(recent_files_path())
+        .map(|text| {
+            text.lines()
+                .filter(|l| !l.is_empty())
+                .map(PathBuf::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn save_recent_files(files: &[PathBuf]) -> std::io::Result<()> {
+    let mut text = String::new();
+    for path in files {
+        text.push_str(&path.to_string_lossy());
+        text.push('\n');
+    }
+    std::fs::write(recent_files_path(), text)
+}
+
 impl TodoApp {
     pub fn load(data_file_path: PathBuf) -> Result<Self, Box<dyn Error>> {
-        Ok(Self {
-            per: TodoAppPersistent::load(&data_file_path)?,
-            temp: TodoAppTemp::new(data_file_path),
-        })
+        let per = TodoAppPersistent::load(&data_file_path)?;
+        Ok(Self::new(per, data_file_path))
+    }
+    pub fn new(per: TodoAppPersistent, data_file_path: PathBuf) -> Self {
+        Self {
+            temp: TodoAppTemp::new(data_file_path, &per),
+            per,
+        }
     }
+    /// Save the persistent data, unless the data file was modified by someone else since we
+    /// last loaded or saved it.
+    ///
+    /// In that case, the user is asked what to do instead.
     pub fn save_persistent(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.data_file_modified_externally() {
+            if !self.temp.per_dirty {
+                // We have nothing to add, so don't clobber their changes
+                return Ok(());
+            }
+            self.temp.modal = Some(ModalPayload::ExternalModification);
+            return Err("The data file was modified by another program".into());
+        }
+        self.overwrite_persistent()
+    }
+    /// Save the persistent data, even if the data file was modified by someone else
+    fn overwrite_persistent(&mut self) -> Result<(), Box<dyn Error>> {
         self.per.save_to_file(&self.temp.data_file_path)?;
         self.temp.per_dirty = false;
+        self.temp.autosave.last_save = Instant::now();
+        self.temp.file_stamp = FileStamp::read(&self.temp.data_file_path).ok();
         Ok(())
     }
+    fn data_file_modified_externally(&self) -> bool {
+        let path = &self.temp.data_file_path;
+        match &self.temp.file_stamp {
+            Some(stamp) => stamp.differs_from(path).unwrap_or(false),
+            None => path.exists(),
+        }
+    }
+    /// Merge the topics from the data file on disk into ours, and save the result
+    fn merge_with_data_file(&mut self) -> Result<(), Box<dyn Error>> {
+        let theirs = TodoAppPersistent::read_from_file(&self.temp.data_file_path)?;
+        merge_topics(&mut self.per.topics, theirs.topics);
+        self.overwrite_persistent()
+    }
+
+    /// Save if there are unsaved changes, and the autosave interval has passed, or the
+    /// window just lost focus (if enabled).
+    fn autosave(&mut self, ctx: &egui::Context) {
+        let focused = ctx.input(|inp| inp.viewport().focused.unwrap_or(true));
+        let focus_lost = self.temp.autosave.was_focused && !focused;
+        self.temp.autosave.was_focused = focused;
+        if !self.temp.per_dirty {
+            return;
+        }
+        let settings = &self.per.settings;
+        let interval = Duration::from_secs(settings.autosave_interval_secs.into());
+        let interval_passed = settings.autosave_interval_secs != 0
+            && self.temp.autosave.last_save.elapsed() >= interval;
+        if interval_passed || (focus_lost && settings.save_on_focus_loss) {
+            if let Err(e) = self.save_persistent() {
+                eprintln!("Autosave error: {e}");
+                // Don't retry every frame
+                self.temp.autosave.last_save = Instant::now();
+            }
+        } else if settings.autosave_interval_secs != 0 {
+            // Make sure we get a frame to autosave in, even if the user is idle
+            ctx.request_repaint_after(
+                interval.saturating_sub(self.temp.autosave.last_save.elapsed()),
+            );
+        }
+    }
     pub fn reload_persistent(&mut self) -> Result<(), Box<dyn Error>> {
         let per = TodoAppPersistent::load(&self.temp.data_file_path)?;
+        self.replace_persistent(per);
+        Ok(())
+    }
+    /// Replace the persistent data with `per`, which is what's in the data file
+    fn replace_persistent(&mut self, per: TodoAppPersistent) {
         self.per = per;
+        self.temp.history.reset(&self.per);
         self.temp.per_dirty = false;
+        self.temp.autosave.last_save = Instant::now();
+        self.temp.file_stamp = FileStamp::read(&self.temp.data_file_path).ok();
+    }
+    /// Continue with the data file at `path`, saving the current one first.
+    ///
+    /// Commands from other setodo processes are then taken for the new data file instead.
+    /// Fails if another instance already has it open.
+    pub fn switch_data_file(
+        &mut self,
+        ctx: &egui::Context,
+        op: DataFileOp,
+        path: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let listener = ipc::claim_endpoint(&self.temp.data_file_path, &path)?;
+        let per = match op {
+            DataFileOp::Open => {
+                let per = TodoAppPersistent::read_from_file(&path)?;
+                self.save_persistent()?;
+                per
+            }
+            DataFileOp::New => {
+                self.save_persistent()?;
+                // Preferences and fonts carry over, the data doesn't
+                let per = TodoAppPersistent {
+                    settings: std::mem::take(&mut self.per.settings),
+                    stored_font_data: self.per.stored_font_data.take(),
+                    ..TodoAppPersistent::default()
+                };
+                if let Err(e) = per.save_to_file(&path) {
+                    self.per.settings = per.settings;
+                    self.per.stored_font_data = per.stored_font_data;
+                    return Err(e);
+                }
+                per
+            }
+            DataFileOp::SaveAs => {
+                self.per.save_to_file(&path)?;
+                std::mem::take(&mut self.per)
+            }
+            DataFileOp::SaveAsDirectory => {
+                dir_store::write(&self.per, &path)?;
+                std::mem::take(&mut self.per)
+            }
+        };
+        self.temp.data_file_path = path;
+        if let Some(listener) = listener {
+            // Stops listening for the old data file
+            self.temp.ipc_commands = Some(ipc::spawn_listener(listener, ctx.clone()));
+        }
+        self.replace_persistent(per);
+        self.temp.state = UiState::Normal;
+        crate::init_app(ctx, self);
+        self.remember_recent_file();
+        Ok(())
+    }
+    fn export(
+        &mut self,
+        format: ExportFormat,
+        scope: ExportScope,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let topics = match scope {
+            ExportScope::SelectedTopic => std::slice::from_ref(
+                tree::get(&self.per.topics, &self.per.topic_sel).ok_or("No topic is selected")?,
+            ),
+            ExportScope::All => &self.per.topics[..],
+        };
+        format.export(topics, path, &self.per.settings)?;
+        self.temp.modal = Some(ModalPayload::InfoMsg(format!(
+            "Exported to '{}'",
+            path.display()
+        )));
         Ok(())
     }
+    fn import(&mut self, format: ImportFormat, path: &Path) -> Result<(), Box<dyn Error>> {
+        let stats = format.import(path, &mut self.per)?;
+        self.temp.per_dirty = true;
+        self.temp.modal = Some(ModalPayload::InfoMsg(stats.to_string()));
+        Ok(())
+    }
+    /// Put the current data file at the top of the recent files list
+    pub fn remember_recent_file(&mut self) {
+        let path = ipc::canonical_path(&self.temp.data_file_path);
+        let recent = &mut self.temp.recent_files;
+        recent.retain(|p| *p != path);
+        recent.insert(0, path);
+        recent.truncate(MAX_RECENT_FILES);
+        if let Err(e) = save_recent_files(recent) {
+            eprintln!("Failed to save recent files list: {e}");
+        }
+    }
 
     fn handle_confirm_action(&mut self, ctx: &egui::Context) {
         let Some(action) = self.temp.confirm_action else {
@@ -194,14 +808,24 @@ impl TodoApp {
         egui::Modal::new("confirm_modal".into()).show(ctx, |ui| {
             let text = match action {
                 ConfirmAction::ClearTopicEntries => {
-                    "Are you sure you want to clear the topic's entries?\nAll entries will be deleted."
+                    "Are you sure you want to clear the topic's entries?\nAll entries will be deleted.".into()
                 }
+                ConfirmAction::RestoreBackup(time) => format!(
+                    "Are you sure you want to restore the backup from {time}?\nAll topics will be replaced with the ones from the backup."
+                ),
             };
             ui.label(text);
             ui.horizontal(|ui| {
                 if ui.button("Yes").clicked() {
                     match action {
                         ConfirmAction::ClearTopicEntries => self.clear_active_topic_entries(),
+                        ConfirmAction::RestoreBackup(time) => {
+                            if let Err(e) = self.restore_backup(time) {
+                                self.temp.modal = Some(ModalPayload::ErrorMsg(format!(
+                                    "Failed to restore backup: {e}"
+                                )));
+                            }
+                        }
                     }
                     self.temp.confirm_action = None;
                 }
@@ -212,6 +836,187 @@ impl TodoApp {
         });
     }
 
+    /// Replace the topics, settings and fonts with the ones in the RON text dump at `path`.
+    ///
+    /// The data file is only written on the next save.
+    fn load_text_dump(&mut self, ctx: &egui::Context, path: &Path) -> Result<(), Box<dyn Error>> {
+        self.per = TodoAppPersistent::from_text(&std::fs::read_to_string(path)?)?;
+        self.temp.per_dirty = true;
+        // The fonts might have changed too
+        crate::init_app(ctx, self);
+        Ok(())
+    }
+
+    /// Replace the topics with the ones from a backup.
+    ///
+    /// This doesn't touch the data file, so it can be undone like any other edit.
+    fn restore_backup(&mut self, time: NaiveDateTime) -> Result<(), Box<dyn Error>> {
+        let path = backup_path(&self.temp.data_file_path, time);
+        let backup = TodoAppPersistent::read_from_file(&path)?;
+        self.per.topics = backup.topics;
+        self.per.topic_sel = backup.topic_sel;
+        Ok(())
+    }
+
+    fn handle_ipc_commands(&mut self, ctx: &egui::Context) {
+        let Some(rx) = &self.temp.ipc_commands else {
+            return;
+        };
+        let incoming: Vec<_> = rx.try_iter().collect();
+        for cmd in incoming {
+            let reply = self.run_ipc_command(ctx, &cmd);
+            cmd.reply(&reply);
+        }
+    }
+    fn run_ipc_command(&mut self, ctx: &egui::Context, incoming: &IncomingCommand) -> Reply {
+        // Only possible if two data file paths hash to the same endpoint name
+        if incoming.data_file_path != ipc::canonical_path(&self.temp.data_file_path) {
+            return Err(format!(
+                "The running instance has '{}' open",
+                self.temp.data_file_path.display()
+            ));
+        }
+        if incoming.expired() {
+            return Err("The command came in too long ago, and was dropped".into());
+        }
+        let mut out = Vec::new();
+        let modified = incoming
+            .cmd
+            .execute(&mut self.per, &mut out)
+            .map_err(|e| e.to_string())?;
+        if modified {
+            self.temp.per_dirty = true;
+        }
+        match incoming.cmd {
+            Command::Open { .. } => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            }
+            // The fonts might have changed too
+            Command::Load { .. } => crate::init_app(ctx, self),
+            _ => {}
+        }
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    fn handle_cmds(&mut self, now: f64) {
+        self.temp.cmd.retain(|cmd| {
+            let mut retain = true;
+            match cmd {
+                Cmd::RemoveTopic { id } => {
+                    self.per.remove_topic(*id);
+                    self.temp.per_dirty = true;
+                    retain = false;
+                }
+                Cmd::GotoEntry { id } => {
+                    if !self.per.goto_entry(*id) {
+                        eprintln!("Entry with id {id} doesn't exist");
+                    }
+                    retain = false;
+                }
+                Cmd::Undo => {
+                    if self.temp.history.undo(&mut self.per, now) {
+                        self.temp.per_dirty = true;
+                    }
+                    retain = false;
+                }
+                Cmd::Redo => {
+                    if self.temp.history.redo(&mut self.per, now) {
+                        self.temp.per_dirty = true;
+                    }
+                    retain = false;
+                }
+                Cmd::MoveTopic {
+                    src,
+                    dst,
+                    placement,
+                } => {
+                    self.per.move_topic(*src, *dst, *placement);
+                    retain = false;
+                }
+                Cmd::MoveEntryToTopic { entry, topic } => {
+                    self.per.move_entry_to_topic(*entry, *topic);
+                    retain = false;
+                }
+                Cmd::FocusTextEdit => {}
+            }
+            retain
+        });
+    }
+
+    fn handle_modal(&mut self, ctx: &egui::Context) {
+        let Some(payload) = &self.temp.modal else {
+            return;
+        };
+        let mut close = false;
+        let mut external_choice = None;
+        egui::Modal::new("modal_popup".into()).show(ctx, |ui| match payload {
+            ModalPayload::ErrorMsg(msg) => {
+                ui.heading("Error");
+                ui.label(msg);
+                if ui.button("Close").clicked() {
+                    close = true;
+                }
+            }
+            ModalPayload::InfoMsg(msg) => {
+                ui.label(msg);
+                if ui.button("Ok").clicked() {
+                    close = true;
+                }
+            }
+            ModalPayload::ExternalModification => {
+                ui.heading("Data file modified");
+                ui.label(
+                    "The data file was modified by another program since it was loaded.\n\
+                     Saving now would overwrite those changes.",
+                );
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Reload")
+                        .on_hover_text("Discard our unsaved changes, and load theirs")
+                        .clicked()
+                    {
+                        external_choice = Some(ExternalModificationChoice::Reload);
+                    }
+                    if ui
+                        .button("Overwrite")
+                        .on_hover_text("Discard their changes, and save ours")
+                        .clicked()
+                    {
+                        external_choice = Some(ExternalModificationChoice::Overwrite);
+                    }
+                    if ui
+                        .button("Merge")
+                        .on_hover_text(
+                            "Combine both versions, and save the result.\n\
+                             Conflicting entries are kept twice.",
+                        )
+                        .clicked()
+                    {
+                        external_choice = Some(ExternalModificationChoice::Merge);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+            }
+        });
+        if close {
+            self.temp.modal = None;
+        }
+        if let Some(choice) = external_choice {
+            self.temp.modal = None;
+            let result = match choice {
+                ExternalModificationChoice::Reload => self.reload_persistent(),
+                ExternalModificationChoice::Overwrite => self.overwrite_persistent(),
+                ExternalModificationChoice::Merge => self.merge_with_data_file(),
+            };
+            if let Err(e) = result {
+                self.temp.modal = Some(ModalPayload::ErrorMsg(e.to_string()));
+            }
+        }
+    }
+
     fn clear_active_topic_entries(&mut self) {
         let Some(topic) = tree::get_mut(&mut self.per.topics, &self.per.topic_sel) else {
             eprintln!("Couldn't get active topic");
@@ -222,12 +1027,33 @@ impl TodoApp {
     }
 }
 
+pub const UNDO_SHORTCUT: KeyboardShortcut =
+    KeyboardShortcut::new(egui::Modifiers::CTRL, egui::Key::Z);
+pub const REDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(
+    egui::Modifiers::CTRL.plus(egui::Modifiers::SHIFT),
+    egui::Key::Z,
+);
+
 impl eframe::App for TodoApp {
     fn on_exit(&mut self, _ctx: Option<&eframe::glow::Context>) {
-        self.save_persistent().unwrap();
+        if let Err(e) = self.save_persistent() {
+            eprintln!("Error saving on exit: {e}");
+            // There is no way to ask the user anymore, so save our version next to theirs
+            let mut path = self.temp.data_file_path.as_os_str().to_owned();
+            path.push(format!(
+                ".conflict-{}",
+                chrono::Local::now().format("%Y%m%d-%H%M%S")
+            ));
+            let path = PathBuf::from(path);
+            match self.per.save_to_file(&path) {
+                Ok(()) => eprintln!("Saved unsaved changes to '{}'", path.display()),
+                Err(e) => eprintln!("Failed to save unsaved changes: {e}"),
+            }
+        }
     }
 
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
+        self.handle_ipc_commands(ctx);
         let [ctrl, btn_r, btn_s, btn_q] = ctx.input(|inp| {
             [
                 inp.modifiers.ctrl,
@@ -251,9 +1077,40 @@ impl eframe::App for TodoApp {
         if ctrl && btn_q {
             ctx.send_viewport_cmd(egui::ViewportCommand::Close);
         }
+        // Text edits have their own undo, so only handle it when none are focused
+        if !ctx.wants_keyboard_input() {
+            ctx.input_mut(|inp| {
+                if inp.consume_shortcut(&REDO_SHORTCUT) {
+                    self.temp.cmd.push(Cmd::Redo);
+                } else if inp.consume_shortcut(&UNDO_SHORTCUT) {
+                    self.temp.cmd.push(Cmd::Undo);
+                }
+            });
+        }
         egui::SidePanel::left("tree_view").show(ctx, |ui| crate::ui::tree_view::ui(ui, self));
         egui::CentralPanel::default().show(ctx, |ui| crate::ui::central_panel::ui(ui, self));
         self.temp.file_dialog.update(ctx);
+        if let Some(op) = self.temp.file_op
+            && let Some(path) = self.temp.file_dialog.take_picked()
+        {
+            self.temp.file_op = None;
+            let result = match op {
+                FileOp::DataFile(op) => self.switch_data_file(ctx, op, path),
+                FileOp::Export { format, scope } => self.export(format, scope, &path),
+                FileOp::Import(format) => self.import(format, &path),
+                FileOp::DumpText => self.per.to_text().and_then(|text| {
+                    std::fs::write(&path, text)?;
+                    Ok(())
+                }),
+                FileOp::LoadText => self.load_text_dump(ctx, &path),
+            };
+            if let Err(e) = result {
+                // Saving the current file may have already asked about something
+                self.temp
+                    .modal
+                    .get_or_insert(ModalPayload::ErrorMsg(e.to_string()));
+            }
+        }
         self.handle_confirm_action(ctx);
         if ctx.input(|inp| inp.key_pressed(egui::Key::Escape)) && !self.temp.esc_was_used {
             ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
@@ -263,33 +1120,13 @@ impl eframe::App for TodoApp {
         }
         self.temp.esc_was_used = false;
         self.temp.action_flags.clear();
-        self.temp.cmd.retain(|cmd| {
-            let mut retain = true;
-            match cmd {
-                Cmd::RemoveTopic { idx } => {
-                    tree::remove(&mut self.per.topics, idx);
-                    self.temp.per_dirty = true;
-                    retain = false;
-                }
-                Cmd::FocusTextEdit => {}
-            }
-            retain
-        });
-        if let Some(payload) = &self.temp.modal {
-            let mut close = false;
-            egui::Modal::new("modal_popup".into()).show(ctx, |ui| match payload {
-                ModalPayload::ErrorMsg(msg) => {
-                    ui.heading("Error");
-                    ui.label(msg);
-                    if ui.button("Close").clicked() {
-                        close = true;
-                    }
-                }
-            });
-            if close {
-                self.temp.modal = None;
-            }
+        let now = ctx.input(|inp| inp.time);
+        self.handle_cmds(now);
+        if self.temp.history.track(&self.per, now) {
+            self.temp.per_dirty = true;
         }
+        self.autosave(ctx);
+        self.handle_modal(ctx);
     }
 }
 
@@ -304,3 +1141,73 @@ pub fn move_task_into_topic(
     topic.entries.push(task);
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{TodoApp, TodoAppPersistent, backup_path, existing_backups, rotate_backups},
+        crate::{
+            cmd::Cmd,
+            data::{Attachment, Entry, Topic, same_content},
+        },
+    };
+
+    #[test]
+    fn test_undo_marks_dirty() {
+        let mut app = TodoApp::new(TodoAppPersistent::default(), "nonexistent.dat".into());
+        app.per.topics.push(Topic::new("Topic".into()));
+        app.temp.history.track(&app.per, 0.0);
+        // As if it was saved since
+        app.temp.per_dirty = false;
+        app.temp.cmd.push(Cmd::Undo);
+        app.handle_cmds(2.0);
+        assert!(app.per.topics.is_empty());
+        assert!(app.temp.per_dirty);
+    }
+
+    #[test]
+    fn test_text_dump_round_trip() {
+        let mut per = TodoAppPersistent::default();
+        let mut topic = Topic::new("Topic".into());
+        let mut entry = Entry::new_task("Task".into());
+        entry.attachments.push(Attachment {
+            filename: "hello.txt".into(),
+            data: b"Hello"[..].into(),
+        });
+        topic.entries.push(entry);
+        per.topics.push(topic);
+        let text = per.to_text().unwrap();
+        assert!(text.contains("\"SGVsbG8=\""));
+        let read = TodoAppPersistent::from_text(&text).unwrap();
+        assert!(same_content(&per.topics, &read.topics));
+        // The msgpack data file format is unchanged
+        let bytes = per.write_to(Vec::new()).unwrap();
+        let read = TodoAppPersistent::read_from(&bytes[..]).unwrap();
+        assert!(same_content(&per.topics, &read.topics));
+    }
+
+    #[test]
+    fn test_rotate_backups() {
+        let dir = std::env::temp_dir().join(format!("setodo-backups-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("data.dat");
+        std::fs::write(&path, "data").unwrap();
+        let hours_ago = |h| chrono::Local::now().naive_local() - chrono::Duration::hours(h);
+        for h in [5, 3, 2] {
+            std::fs::write(backup_path(&path, hours_ago(h)), "old").unwrap();
+        }
+        // The last one is old enough for a new backup, and the count is lowered
+        rotate_backups(&path, 2).unwrap();
+        let backups = existing_backups(&path);
+        assert_eq!(backups.len(), 2);
+        assert_eq!(
+            std::fs::read(backup_path(&path, backups[0])).unwrap(),
+            b"data"
+        );
+        // Too soon for another one
+        rotate_backups(&path, 2).unwrap();
+        assert_eq!(existing_backups(&path), backups);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
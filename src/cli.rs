@@ -0,0 +1,421 @@
+use {
+    crate::{
+        app::TodoAppPersistent,
+        data::{Entry, EntryKind, Id, Recurrence, Topic, find_entry_mut},
+        tree,
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        error::Error,
+        io::Write,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Headless subcommand, for scripting the todo store without opening the window.
+///
+/// If setodo is already running, the command is sent to it instead.
+#[derive(Serialize, Deserialize)]
+pub enum Command {
+    /// Add a task to the topic at `topic` (a `/` separated path), creating missing topics
+    Add { topic: String, title: String },
+    /// List the entries of a topic and its subtopics, or of all topics
+    List { topic: Option<String>, all: bool },
+    /// Mark the task with the given id (or unique id prefix) as done
+    Done { id: String },
+    /// Print everything about the entry with the given id (or unique id prefix)
+    Show { id: String },
+    /// List the entries whose title or description contain a string (case insensitive)
+    Search { query: String },
+    /// Print the topic tree
+    Tree,
+    /// Select the topic at `topic` in the window
+    Open { topic: String },
+    /// Write everything as text to a file, or print it if there is no file
+    Dump { file: Option<PathBuf> },
+    /// Replace everything with the contents of a text dump
+    Load { file: PathBuf },
+}
+
+pub const COMMANDS_HELP: &str = "\
+Commands:
+  add <topic/path> <title>   Add a task, creating missing topics
+  list [--all] [topic/path]  List open tasks (--all: all entries)
+  done <id>                  Mark a task as done
+  show <id>                  Show all details of an entry
+  search <text>              Find entries by title or description
+  tree                       Print the topic tree
+  open <topic/path>          Show the window with this topic selected
+  dump [file]                Write everything as text (RON), to stdout by default
+  load <file>                Replace everything with a text dump
+
+Ids can be abbreviated, as long as they stay unique.";
+
+impl Command {
+    pub fn parse(name: &str, args: &[String]) -> Result<Self, String> {
+        let cmd = match (name, args) {
+            ("add", [topic, title]) => Self::Add {
+                topic: topic.clone(),
+                title: title.clone(),
+            },
+            ("list", args) => {
+                let all = args.iter().any(|arg| arg == "--all");
+                let mut rest = args.iter().filter(|arg| *arg != "--all");
+                let topic = rest.next().cloned();
+                if rest.next().is_some() {
+                    return Err(format!("Too many arguments for 'list'\n\n{COMMANDS_HELP}"));
+                }
+                Self::List { topic, all }
+            }
+            ("done", [id]) => Self::Done { id: id.clone() },
+            ("show", [id]) => Self::Show { id: id.clone() },
+            ("search", [query]) => Self::Search {
+                query: query.clone(),
+            },
+            ("tree", []) => Self::Tree,
+            ("open", [topic]) => Self::Open {
+                topic: topic.clone(),
+            },
+            // Absolute, because a running instance might have a different working directory
+            ("dump", []) => Self::Dump { file: None },
+            ("dump", [file]) => Self::Dump {
+                file: Some(absolute(file)?),
+            },
+            ("load", [file]) => Self::Load {
+                file: absolute(file)?,
+            },
+            ("add" | "done" | "show" | "search" | "tree" | "open" | "dump" | "load", _) => {
+                return Err(format!(
+                    "Wrong number of arguments for '{name}'\n\n{COMMANDS_HELP}"
+                ));
+            }
+            _ => return Err(format!("Unknown command '{name}'\n\n{COMMANDS_HELP}")),
+        };
+        Ok(cmd)
+    }
+
+    /// Run the command on `per`, writing human readable output to `out`.
+    ///
+    /// Returns whether `per` was modified.
+    pub fn execute(
+        &self,
+        per: &mut TodoAppPersistent,
+        out: &mut impl Write,
+    ) -> Result<bool, Box<dyn Error>> {
+        match self {
+            Self::Add { topic, title } => {
+                let topic = topic_by_path_or_create(&mut per.topics, topic)?;
+                let entry = Entry::new_task(title.clone());
+                writeln!(out, "{}", entry.id)?;
+                topic.entries.push(entry);
+                return Ok(true);
+            }
+            Self::List { topic, all } => {
+                let (prefix, topics) = match topic {
+                    Some(path) => {
+                        let indices = topic_path_by_names(&per.topics, path)
+                            .ok_or_else(|| format!("No topic '{path}'"))?;
+                        let topic = tree::get(&per.topics, &indices).unwrap();
+                        let prefix = path.trim_matches('/').to_owned();
+                        (prefix, std::slice::from_ref(topic))
+                    }
+                    None => (String::new(), per.topics.as_slice()),
+                };
+                let mut result = Ok(());
+                walk_entries(topics, &parent_of(&prefix), &mut |path, entry| {
+                    let open = entry.kind == EntryKind::Task && !entry.done;
+                    if (*all || open) && result.is_ok() {
+                        result = write_entry_line(out, path, entry);
+                    }
+                });
+                result?;
+            }
+            Self::Done { id } => {
+                let id = resolve_entry_id(&per.topics, id)?;
+                let today = chrono::Local::now().date_naive();
+                let entry = find_entry_mut(&mut per.topics, id).unwrap();
+                if entry.kind != EntryKind::Task {
+                    return Err(format!("'{}' is not a task", entry.title).into());
+                }
+                if entry.done {
+                    writeln!(out, "'{}' was already done", entry.title)?;
+                    return Ok(false);
+                }
+                entry.done = true;
+                entry.roll_forward(today);
+                match entry.due {
+                    Some(due) if entry.recurrence.is_some() => {
+                        writeln!(out, "Done: '{}'. Next due: {due}", entry.title)?;
+                    }
+                    _ => writeln!(out, "Done: '{}'", entry.title)?,
+                }
+                return Ok(true);
+            }
+            Self::Show { id } => {
+                let id = resolve_entry_id(&per.topics, id)?;
+                let mut result = Ok(());
+                walk_entries(&per.topics, "", &mut |path, entry| {
+                    if entry.id == id {
+                        result = write_entry_details(out, path, entry);
+                    }
+                });
+                result?;
+            }
+            Self::Search { query } => {
+                let query = query.to_lowercase();
+                let mut result = Ok(());
+                walk_entries(&per.topics, "", &mut |path, entry| {
+                    let matches = entry.title.to_lowercase().contains(&query)
+                        || entry.desc.to_lowercase().contains(&query);
+                    if matches && result.is_ok() {
+                        result = write_entry_line(out, path, entry);
+                    }
+                });
+                result?;
+            }
+            Self::Tree => write_tree(out, &per.topics, 0)?,
+            Self::Open { topic } => {
+                let indices = topic_path_by_names(&per.topics, topic)
+                    .ok_or_else(|| format!("No topic '{topic}'"))?;
+                per.topic_sel = indices;
+            }
+            Self::Dump { file } => {
+                let text = per.to_text()?;
+                match file {
+                    Some(file) => std::fs::write(file, text)?,
+                    None => out.write_all(text.as_bytes())?,
+                }
+            }
+            Self::Load { file } => {
+                *per = TodoAppPersistent::from_text(&std::fs::read_to_string(file)?)?;
+                writeln!(out, "Loaded {}", file.display())?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Load the data file, run `cmd` on it, and save it again if it was modified
+pub fn run(cmd: &Command, data_file_path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut per = TodoAppPersistent::load(data_file_path)?;
+    let mut stdout = std::io::stdout().lock();
+    if cmd.execute(&mut per, &mut stdout)? {
+        per.save_to_file(data_file_path)?;
+    }
+    Ok(())
+}
+
+fn absolute(path: &str) -> Result<PathBuf, String> {
+    std::path::absolute(path).map_err(|e| format!("Invalid path '{path}': {e}"))
+}
+
+/// Call `f` for each topic in the tree, along with its `/` separated path
+fn walk(topics: &[Topic], parent: &str, f: &mut impl FnMut(&str, &Topic)) {
+    for topic in topics {
+        let path = if parent.is_empty() {
+            topic.name.clone()
+        } else {
+            format!("{parent}/{}", topic.name)
+        };
+        f(&path, topic);
+        walk(&topic.children, &path, f);
+    }
+}
+
+/// Like [`walk`], but calls `f` for every entry of the topics, subtasks included
+fn walk_entries(topics: &[Topic], parent: &str, f: &mut impl FnMut(&str, &Entry)) {
+    fn inner(entries: &[Entry], path: &str, f: &mut impl FnMut(&str, &Entry)) {
+        for entry in entries {
+            f(path, entry);
+            inner(&entry.children, path, f);
+        }
+    }
+    walk(topics, parent, &mut |path, topic| {
+        inner(&topic.entries, path, f);
+    });
+}
+
+fn parent_of(path: &str) -> String {
+    path.rsplit_once('/')
+        .map_or_else(String::new, |(parent, _)| parent.to_owned())
+}
+
+fn topic_path_by_names(topics: &[Topic], path: &str) -> Option<Vec<usize>> {
+    let mut indices = Vec::new();
+    let mut nodes = topics;
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        let idx = nodes.iter().position(|topic| topic.name == name)?;
+        indices.push(idx);
+        nodes = &nodes[idx].children;
+    }
+    (!indices.is_empty()).then_some(indices)
+}
+
+fn topic_by_path_or_create<'t>(
+    mut topics: &'t mut Vec<Topic>,
+    path: &str,
+) -> Result<&'t mut Topic, Box<dyn Error>> {
+    let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+    if names.peek().is_none() {
+        return Err("The topic path is empty".into());
+    }
+    loop {
+        let name = names.next().unwrap();
+        let idx = if let Some(idx) = topics.iter().position(|topic| topic.name == name) {
+            idx
+        } else {
+            topics.push(Topic::new(name.to_owned()));
+            topics.len() - 1
+        };
+        if names.peek().is_none() {
+            return Ok(&mut topics[idx]);
+        }
+        topics = &mut topics[idx].children;
+    }
+}
+
+/// Find the id of the single entry whose id starts with `prefix`
+fn resolve_entry_id(topics: &[Topic], prefix: &str) -> Result<Id, Box<dyn Error>> {
+    let prefix = prefix.to_lowercase();
+    let mut matches = Vec::new();
+    walk_entries(topics, "", &mut |_, entry| {
+        if entry.id.to_string().starts_with(&prefix) {
+            matches.push(entry.id);
+        }
+    });
+    match matches[..] {
+        [id] => Ok(id),
+        [] => Err(format!("No entry with id '{prefix}'").into()),
+        _ => Err(format!("The id '{prefix}' is ambiguous").into()),
+    }
+}
+
+const fn status_marker(entry: &Entry) -> &'static str {
+    match entry.kind {
+        EntryKind::Task if entry.done => "[x]",
+        EntryKind::Task => "[ ]",
+        EntryKind::Info => "(i)",
+    }
+}
+
+fn write_entry_line(out: &mut impl Write, path: &str, entry: &Entry) -> std::io::Result<()> {
+    write!(
+        out,
+        "{} {} {path}: {}",
+        entry.id,
+        status_marker(entry),
+        entry.title
+    )?;
+    if let Some(due) = entry.due {
+        write!(out, " (due {due})")?;
+    }
+    writeln!(out)
+}
+
+fn write_entry_details(out: &mut impl Write, path: &str, entry: &Entry) -> std::io::Result<()> {
+    writeln!(out, "{} {}", status_marker(entry), entry.title)?;
+    writeln!(out, "Id:         {}", entry.id)?;
+    writeln!(out, "Topic:      {path}")?;
+    if let Some(due) = entry.due {
+        writeln!(out, "Due:        {due}")?;
+    }
+    if let Some(scheduled) = entry.scheduled {
+        writeln!(out, "Scheduled:  {scheduled}")?;
+    }
+    if let Some(recurrence) = entry.recurrence {
+        let desc = match recurrence {
+            Recurrence::Days(n) => format!("every {n} days"),
+            Recurrence::Weekdays => "every weekday".into(),
+            Recurrence::MonthDay(day) => format!("monthly, on day {day}"),
+        };
+        writeln!(out, "Repeats:    {desc}")?;
+    }
+    if !entry.completion_log.is_empty() {
+        writeln!(out, "Completed:  {} times", entry.completion_log.len())?;
+    }
+    if let Some((done, total)) = entry.subtask_progress() {
+        writeln!(out, "Subtasks:   {done}/{total} done")?;
+    }
+    for attachment in &entry.attachments {
+        writeln!(out, "Attachment: {}", attachment.filename.display())?;
+    }
+    if !entry.desc.is_empty() {
+        writeln!(out, "\n{}", entry.desc)?;
+    }
+    Ok(())
+}
+
+fn write_tree(out: &mut impl Write, topics: &[Topic], depth: usize) -> std::io::Result<()> {
+    for topic in topics {
+        let open = topic
+            .entries
+            .iter()
+            .filter(|en| en.kind == EntryKind::Task && !en.done)
+            .count();
+        write!(out, "{:indent$}{}", "", topic.name, indent = depth * 2)?;
+        if open > 0 {
+            write!(out, " ({open})")?;
+        }
+        writeln!(out)?;
+        write_tree(out, &topic.children, depth + 1)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::Command,
+        crate::{
+            app::TodoAppPersistent,
+            data::{Entry, Topic},
+        },
+    };
+
+    fn exec(per: &mut TodoAppPersistent, name: &str, args: &[&str]) -> (bool, String) {
+        let args: Vec<String> = args.iter().map(|&arg| arg.to_owned()).collect();
+        let cmd = Command::parse(name, &args).unwrap();
+        let mut out = Vec::new();
+        let modified = cmd.execute(per, &mut out).unwrap();
+        (modified, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_cli_add_list_done() {
+        let mut per = TodoAppPersistent::default();
+        per.topics.push(Topic::new("work".into()));
+        let (modified, id) = exec(&mut per, "add", &["work/project", "write report"]);
+        assert!(modified);
+        assert_eq!(per.topics.len(), 1);
+        assert_eq!(per.topics[0].children[0].name, "project");
+        let (_, listed) = exec(&mut per, "list", &["work"]);
+        assert!(listed.contains("[ ] work/project: write report"));
+        // Abbreviated ids work too
+        let (modified, _) = exec(&mut per, "done", &[&id.trim()[..6]]);
+        assert!(modified);
+        let (_, listed) = exec(&mut per, "list", &[]);
+        assert!(listed.is_empty());
+        let (_, listed) = exec(&mut per, "list", &["--all"]);
+        assert!(listed.contains("[x] work/project: write report"));
+    }
+
+    #[test]
+    fn test_cli_subtasks() {
+        let mut per = TodoAppPersistent::default();
+        let mut topic = Topic::new("work".into());
+        let mut task = Entry::new_task("release".into());
+        let sub = Entry::new_task("write changelog".into());
+        let id = sub.id.to_string();
+        task.children.push(sub);
+        topic.entries.push(task);
+        per.topics.push(topic);
+        let (_, listed) = exec(&mut per, "list", &[]);
+        assert!(listed.contains("[ ] work: write changelog"));
+        let (_, shown) = exec(&mut per, "show", &[&id]);
+        assert!(shown.contains("write changelog"));
+        let (modified, _) = exec(&mut per, "done", &[&id]);
+        assert!(modified);
+        assert!(per.topics[0].entries[0].children[0].done);
+    }
+}
@@ -1,4 +1,25 @@
+use crate::{app::Placement, data::Id};
+
 pub enum Cmd {
-    RemoveTopic { idx: Vec<usize> },
+    RemoveTopic {
+        id: Id,
+    },
     FocusTextEdit,
+    /// Move a topic next to or into another one, like when it's dragged there
+    MoveTopic {
+        src: Id,
+        dst: Id,
+        placement: Placement,
+    },
+    /// Move an entry to the end of a topic, like when it's dragged there
+    MoveEntryToTopic {
+        entry: Id,
+        topic: Id,
+    },
+    /// Jump to the entry with this id, wherever it is
+    GotoEntry {
+        id: Id,
+    },
+    Undo,
+    Redo,
 }
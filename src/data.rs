@@ -1,9 +1,171 @@
 use {
+    chrono::{Datelike as _, Days, Months, NaiveDate, Weekday},
     serde::{Deserialize, Serialize},
-    std::path::PathBuf,
+    std::{
+        collections::{HashSet, hash_map::RandomState},
+        hash::{BuildHasher as _, Hasher as _},
+        path::PathBuf,
+        sync::{
+            Arc,
+            atomic::{AtomicU64, Ordering},
+        },
+    },
 };
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Persistent unique identifier of a topic or an entry.
+///
+/// Unlike index paths, ids stay valid when things are moved around.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Id(pub u64);
+
+impl Id {
+    /// Placeholder for data from before ids existed. Replaced by [`assign_ids`] on load.
+    pub const UNASSIGNED: Self = Self(0);
+    /// Generate a new random id
+    pub fn new() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        Self(hasher.finish().max(1))
+    }
+}
+
+/// FNV-1a hash of `bytes`.
+///
+/// Unlike the std hashers, it's the same on every run and with every build.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl std::fmt::Display for Id {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl std::str::FromStr for Id {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(Self)
+    }
+}
+
+/// Whether two topic trees have the same content.
+///
+/// Unlike `==`, this ignores UI state like the selected entry of a topic.
+#[cfg(test)]
+pub fn same_content(a: &[Topic], b: &[Topic]) -> bool {
+    topics_eq(a, b, &|a, b| a == b)
+}
+
+/// Like [`same_content`], for checking `a` against an earlier clone of it.
+///
+/// Attachments only count as the same if they share their data, so their bytes don't have to
+/// be compared. Editing never modifies attachment data in place, so this is enough.
+pub fn same_content_as_clone(a: &[Topic], b: &[Topic]) -> bool {
+    topics_eq(a, b, &|a, b| {
+        a.filename == b.filename && Arc::ptr_eq(&a.data, &b.data)
+    })
+}
+
+fn topics_eq(
+    a: &[Topic],
+    b: &[Topic],
+    attachment_eq: &impl Fn(&Attachment, &Attachment) -> bool,
+) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.id == b.id
+                && a.name == b.name
+                && a.desc == b.desc
+                && entries_eq(&a.entries, &b.entries, attachment_eq)
+                && topics_eq(&a.children, &b.children, attachment_eq)
+        })
+}
+
+fn entries_eq(
+    a: &[Entry],
+    b: &[Entry],
+    attachment_eq: &impl Fn(&Attachment, &Attachment) -> bool,
+) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            // Destructured, so new fields can't be forgotten here
+            let Entry {
+                title,
+                desc,
+                done,
+                attachments,
+                kind,
+                due,
+                scheduled,
+                recurrence,
+                completion_log,
+                id,
+                children,
+            } = a;
+            *id == b.id
+                && *title == b.title
+                && *desc == b.desc
+                && *done == b.done
+                && *kind == b.kind
+                && *due == b.due
+                && *scheduled == b.scheduled
+                && *recurrence == b.recurrence
+                && *completion_log == b.completion_log
+                && attachments.len() == b.attachments.len()
+                && attachments
+                    .iter()
+                    .zip(&b.attachments)
+                    .all(|(a, b)| attachment_eq(a, b))
+                && entries_eq(children, &b.children, attachment_eq)
+        })
+}
+
+/// The entry with the given id, wherever it is in the tree, subtasks included
+pub fn find_entry_mut(topics: &mut [Topic], id: Id) -> Option<&mut Entry> {
+    fn in_entries(entries: &mut [Entry], id: Id) -> Option<&mut Entry> {
+        entries.iter_mut().find_map(|en| {
+            if en.id == id {
+                Some(en)
+            } else {
+                in_entries(&mut en.children, id)
+            }
+        })
+    }
+    topics.iter_mut().find_map(|topic| {
+        in_entries(&mut topic.entries, id).or_else(|| find_entry_mut(&mut topic.children, id))
+    })
+}
+
+/// Give every topic and entry that lacks one (or shares one with another) a fresh id
+pub fn assign_ids(topics: &mut [Topic]) {
+    fn inner(topics: &mut [Topic], seen: &mut HashSet<Id>) {
+        for topic in topics {
+            if topic.id == Id::UNASSIGNED || !seen.insert(topic.id) {
+                topic.id = Id::new();
+                seen.insert(topic.id);
+            }
+            inner_entries(&mut topic.entries, seen);
+            inner(&mut topic.children, seen);
+        }
+    }
+    fn inner_entries(entries: &mut [Entry], seen: &mut HashSet<Id>) {
+        for en in entries {
+            if en.id == Id::UNASSIGNED || !seen.insert(en.id) {
+                en.id = Id::new();
+                seen.insert(en.id);
+            }
+            inner_entries(&mut en.children, seen);
+        }
+    }
+    inner(topics, &mut HashSet::new());
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Topic {
     pub name: String,
     pub desc: String,
@@ -13,27 +175,159 @@ pub struct Topic {
     /// Child topics, if any
     #[serde(default)]
     pub children: Vec<Self>,
+    #[serde(default)]
+    pub id: Id,
+    /// Entries selected along with `task_sel`, to act on all of them at once. Not saved.
+    #[serde(skip)]
+    pub extra_sel: HashSet<Id>,
 }
 
 impl Topic {
-    pub fn new_unnamed() -> Self {
+    pub fn new(name: String) -> Self {
         Self {
-            name: "New unnamed topic".into(),
+            name,
             desc: String::new(),
             entries: Vec::new(),
             task_sel: None,
             children: Vec::new(),
+            id: Id::new(),
+            extra_sel: HashSet::new(),
+        }
+    }
+    pub fn new_unnamed() -> Self {
+        Self::new("New unnamed topic".into())
+    }
+    /// Index of the entry with the given id
+    pub fn entry_pos(&self, id: Id) -> Option<usize> {
+        self.entries.iter().position(|en| en.id == id)
+    }
+    /// Insert an entry right after the selected one (or at the top), and select it
+    pub fn insert_task_after_sel(&mut self, entry: Entry) {
+        self.entries
+            .insert(self.task_sel.map_or(0, |idx| idx + 1), entry);
+        match &mut self.task_sel {
+            Some(sel) => {
+                if *sel + 1 < self.entries.len() {
+                    *sel += 1;
+                }
+            }
+            None => {
+                self.task_sel = Some(0);
+            }
         }
     }
+    /// Select only the entry at `idx`
+    pub fn select_entry(&mut self, idx: usize) {
+        self.task_sel = Some(idx);
+        self.extra_sel.clear();
+    }
+    /// Add the entry at `idx` to the selection (and make it the current one), or take it out
+    pub fn toggle_entry_sel(&mut self, idx: usize) {
+        let Some(id) = self.entries.get(idx).map(|en| en.id) else {
+            return;
+        };
+        if !self.is_entry_selected(idx) {
+            self.extra_sel.extend(self.selected_entry_id());
+            self.task_sel = Some(idx);
+        } else if self.task_sel == Some(idx) {
+            // The next one in the list becomes the current one
+            self.task_sel = self
+                .selected_entry_positions()
+                .into_iter()
+                .find(|&pos| pos != idx);
+            if let Some(sel_id) = self.selected_entry_id() {
+                self.extra_sel.remove(&sel_id);
+            }
+        } else {
+            self.extra_sel.remove(&id);
+        }
+    }
+    /// Select the entries between the current one and the one at `idx`
+    pub fn select_entry_range(&mut self, idx: usize) {
+        // The selection may be out of bounds in a hand-edited text dump
+        let anchor = match self.task_sel {
+            Some(sel) if sel < self.entries.len() => sel,
+            _ => *self.task_sel.insert(idx),
+        };
+        let range = anchor.min(idx)..=anchor.max(idx);
+        self.extra_sel = self
+            .entries
+            .get(range)
+            .unwrap_or_default()
+            .iter()
+            .map(|en| en.id)
+            .filter(|&id| self.selected_entry_id() != Some(id))
+            .collect();
+    }
+    pub fn is_entry_selected(&self, idx: usize) -> bool {
+        self.task_sel == Some(idx)
+            || self
+                .entries
+                .get(idx)
+                .is_some_and(|en| self.extra_sel.contains(&en.id))
+    }
+    /// Positions of the selected entries, in list order
+    pub fn selected_entry_positions(&self) -> Vec<usize> {
+        (0..self.entries.len())
+            .filter(|&idx| self.is_entry_selected(idx))
+            .collect()
+    }
+    /// Take out all selected entries.
+    ///
+    /// The entry after them (or the last one) becomes the selected one.
+    pub fn take_selected_entries(&mut self) -> Vec<Entry> {
+        let positions = self.selected_entry_positions();
+        let mut taken = Vec::new();
+        for &pos in positions.iter().rev() {
+            taken.push(self.entries.remove(pos));
+        }
+        taken.reverse();
+        self.extra_sel.clear();
+        self.task_sel = positions
+            .first()
+            .filter(|_| !self.entries.is_empty())
+            .map(|&pos| pos.min(self.entries.len() - 1));
+        taken
+    }
+    fn selected_entry_id(&self) -> Option<Id> {
+        self.task_sel
+            .and_then(|idx| self.entries.get(idx))
+            .map(|en| en.id)
+    }
+    /// Move the entry at `src` to position `pos`, as counted before the move.
+    ///
+    /// The selection stays on the same entry.
+    pub fn move_entry(&mut self, src: usize, pos: usize) {
+        if src >= self.entries.len() {
+            return;
+        }
+        let sel_id = self.selected_entry_id();
+        let entry = self.entries.remove(src);
+        let pos = if pos > src { pos - 1 } else { pos };
+        self.entries.insert(pos.min(self.entries.len()), entry);
+        self.task_sel = sel_id.and_then(|id| self.entry_pos(id));
+    }
+    /// Take out the entry with the given id.
+    ///
+    /// The selection stays on the same entry, unless it's the one taken out.
+    pub fn take_entry(&mut self, id: Id) -> Option<Entry> {
+        let sel_id = self.selected_entry_id();
+        let entry = self.entries.remove(self.entry_pos(id)?);
+        self.task_sel = sel_id.and_then(|id| self.entry_pos(id));
+        Some(entry)
+    }
 }
 
 impl crate::tree::Node for Topic {
+    fn children(&self) -> &[Self] {
+        &self.children
+    }
     fn children_mut(&mut self) -> &mut Vec<Self> {
         &mut self.children
     }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
 pub struct Entry {
     pub title: String,
     pub desc: String,
@@ -44,9 +338,158 @@ pub struct Entry {
     pub attachments: Vec<Attachment>,
     #[serde(default)]
     pub kind: EntryKind,
+    /// Date by which this task should be done
+    #[serde(default)]
+    pub due: Option<NaiveDate>,
+    /// Date on which work on this task is planned to start
+    #[serde(default)]
+    pub scheduled: Option<NaiveDate>,
+    /// If set, checking off this task rolls it forward instead of marking it done
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    /// Dates on which this (recurring) task was completed
+    #[serde(default)]
+    pub completion_log: Vec<NaiveDate>,
+    #[serde(default)]
+    pub id: Id,
+    /// Subtasks, shown indented below this entry
+    #[serde(default)]
+    pub children: Vec<Self>,
+}
+
+/// How many days ahead of the due date a task counts as "due soon"
+const DUE_SOON_DAYS: i64 = 3;
+
+impl Entry {
+    pub fn new_task(title: String) -> Self {
+        Self {
+            title,
+            kind: EntryKind::Task,
+            id: Id::new(),
+            ..Self::default()
+        }
+    }
+    /// Urgency of this entry relative to `today`, if it's an unfinished task with a due date
+    pub fn due_status(&self, today: NaiveDate) -> Option<DueStatus> {
+        if self.kind != EntryKind::Task || self.done {
+            return None;
+        }
+        let days_left = (self.due? - today).num_days();
+        match days_left {
+            ..0 => Some(DueStatus::Overdue),
+            0 => Some(DueStatus::Today),
+            1..=DUE_SOON_DAYS => Some(DueStatus::Soon),
+            _ => None,
+        }
+    }
+    /// Roll a recurring task forward to its next occurrence after being completed on `today`.
+    ///
+    /// The due date moves to the next occurrence that lies in the future, and the scheduled
+    /// date (if any) moves along by the same amount.
+    /// Does nothing if the entry has no recurrence rule.
+    pub fn roll_forward(&mut self, today: NaiveDate) {
+        let Some(recurrence) = self.recurrence else {
+            return;
+        };
+        self.completion_log.push(today);
+        self.done = false;
+        // The steps have to be done again for the next occurrence
+        uncheck_all(&mut self.children);
+        let base = self.due.unwrap_or(today);
+        let mut next = recurrence.next_after(base);
+        while next <= today {
+            next = recurrence.next_after(next);
+        }
+        if let Some(scheduled) = &mut self.scheduled {
+            *scheduled += next - base;
+        }
+        self.due = Some(next);
+    }
+    /// How many of the subtasks are done, out of how many. `None` if there are none.
+    pub fn subtask_progress(&self) -> Option<(usize, usize)> {
+        let tasks = || self.children.iter().filter(|en| en.kind == EntryKind::Task);
+        let total = tasks().count();
+        (total > 0).then(|| (tasks().filter(|en| en.done).count(), total))
+    }
+    /// Mark this task as done if all of its subtasks are, and likewise for the subtasks.
+    ///
+    /// Returns whether anything was marked.
+    pub fn complete_if_subtasks_done(&mut self, today: NaiveDate) -> bool {
+        let mut changed = false;
+        for child in &mut self.children {
+            changed |= child.complete_if_subtasks_done(today);
+        }
+        if self.kind == EntryKind::Task
+            && !self.done
+            && self
+                .subtask_progress()
+                .is_some_and(|(done, total)| done == total)
+        {
+            self.done = true;
+            self.roll_forward(today);
+            changed = true;
+        }
+        changed
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recurrence {
+    /// Every `n` days
+    Days(u32),
+    /// Every Monday to Friday
+    Weekdays,
+    /// On a given day of every month. Clamped to the last day in shorter months.
+    MonthDay(u32),
+}
+
+impl Recurrence {
+    /// The first occurrence strictly after `date`
+    pub fn next_after(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Days(n) => date + Days::new(n.max(1).into()),
+            Self::Weekdays => {
+                let mut next = date + Days::new(1);
+                while matches!(next.weekday(), Weekday::Sat | Weekday::Sun) {
+                    next = next + Days::new(1);
+                }
+                next
+            }
+            Self::MonthDay(day) => {
+                let this_month = clamped_month_day(date, day);
+                if this_month > date {
+                    this_month
+                } else {
+                    clamped_month_day(date.with_day(1).unwrap() + Months::new(1), day)
+                }
+            }
+        }
+    }
+}
+
+/// Mark `entries` and all of their subtasks as not done
+fn uncheck_all(entries: &mut [Entry]) {
+    for en in entries {
+        en.done = false;
+        uncheck_all(&mut en.children);
+    }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+/// The date with the given day in the same month as `date`, clamped to the last day of the month
+fn clamped_month_day(date: NaiveDate, day: u32) -> NaiveDate {
+    let first = date.with_day(1).unwrap();
+    let last = (first + Months::new(1)).pred_opt().unwrap();
+    first.with_day(day.clamp(1, last.day())).unwrap()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DueStatus {
+    Overdue,
+    Today,
+    Soon,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
 pub enum EntryKind {
     /// Toggleable checkmark
     #[default]
@@ -55,8 +498,152 @@ pub enum EntryKind {
     Info,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Attachment {
     pub filename: PathBuf,
-    pub data: Vec<u8>,
+    /// Shared, so that undo history snapshots don't have to copy it
+    #[serde(with = "attachment_data")]
+    pub data: Arc<[u8]>,
+}
+
+/// Attachment data is base64 in human readable formats, instead of a huge list of numbers
+mod attachment_data {
+    use {
+        base64::{Engine as _, prelude::BASE64_STANDARD},
+        serde::{Deserialize as _, Deserializer, Serialize as _, Serializer, de::Error as _},
+        std::sync::Arc,
+    };
+
+    pub fn serialize<S: Serializer>(data: &Arc<[u8]>, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&BASE64_STANDARD.encode(data))
+        } else {
+            data.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<[u8]>, D::Error> {
+        if deserializer.is_human_readable() {
+            let text = String::deserialize(deserializer)?;
+            BASE64_STANDARD
+                .decode(text)
+                .map(Into::into)
+                .map_err(D::Error::custom)
+        } else {
+            Arc::deserialize(deserializer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{Entry, Recurrence, Topic},
+        chrono::NaiveDate,
+    };
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_weekdays_skip_weekend() {
+        // 2024-03-08 is a Friday
+        assert_eq!(
+            Recurrence::Weekdays.next_after(date(2024, 3, 8)),
+            date(2024, 3, 11)
+        );
+    }
+    #[test]
+    fn test_month_day_clamps() {
+        assert_eq!(
+            Recurrence::MonthDay(31).next_after(date(2024, 1, 31)),
+            date(2024, 2, 29)
+        );
+        assert_eq!(
+            Recurrence::MonthDay(31).next_after(date(2024, 2, 29)),
+            date(2024, 3, 31)
+        );
+        assert_eq!(
+            Recurrence::MonthDay(15).next_after(date(2024, 3, 10)),
+            date(2024, 3, 15)
+        );
+    }
+    #[test]
+    fn test_roll_forward_skips_past_occurrences() {
+        let mut en = Entry {
+            due: Some(date(2024, 3, 1)),
+            scheduled: Some(date(2024, 2, 28)),
+            recurrence: Some(Recurrence::Days(7)),
+            done: true,
+            ..Entry::default()
+        };
+        en.roll_forward(date(2024, 3, 20));
+        assert!(!en.done);
+        assert_eq!(en.due, Some(date(2024, 3, 22)));
+        assert_eq!(en.scheduled, Some(date(2024, 3, 20)));
+        assert_eq!(en.completion_log, vec![date(2024, 3, 20)]);
+    }
+
+    #[test]
+    fn test_complete_if_subtasks_done() {
+        let mut parent = Entry::new_task("Parent".into());
+        let mut child = Entry::new_task("Child".into());
+        child.children.push(Entry::new_task("Grandchild".into()));
+        parent.children.push(child);
+        parent.children.push(Entry::new_task("Other child".into()));
+        let today = date(2024, 3, 13);
+        assert!(!parent.complete_if_subtasks_done(today));
+        parent.children[0].children[0].done = true;
+        assert!(parent.complete_if_subtasks_done(today));
+        assert_eq!(parent.subtask_progress(), Some((1, 2)));
+        assert!(!parent.done);
+        parent.children[1].done = true;
+        // Recurring, so everything is rolled forward instead
+        parent.recurrence = Some(Recurrence::Days(1));
+        assert!(parent.complete_if_subtasks_done(today));
+        assert!(!parent.done);
+        assert_eq!(parent.subtask_progress(), Some((0, 2)));
+        assert_eq!(parent.completion_log, [today]);
+    }
+
+    #[test]
+    fn test_multi_select() {
+        let mut topic = Topic::new("Topic".into());
+        for title in ["a", "b", "c", "d"] {
+            topic.entries.push(Entry::new_task(title.into()));
+        }
+        topic.select_entry(1);
+        topic.select_entry_range(3);
+        assert_eq!(topic.selected_entry_positions(), [1, 2, 3]);
+        topic.toggle_entry_sel(2);
+        topic.toggle_entry_sel(0);
+        assert_eq!(topic.selected_entry_positions(), [0, 1, 3]);
+        assert_eq!(topic.task_sel, Some(0));
+        let taken = topic.take_selected_entries();
+        let titles: Vec<_> = taken.iter().map(|en| en.title.as_str()).collect();
+        assert_eq!(titles, ["a", "b", "d"]);
+        assert_eq!(topic.task_sel, Some(0));
+        assert_eq!(topic.entries[0].title, "c");
+        // As loaded from a hand-edited text dump
+        topic.task_sel = Some(10);
+        topic.select_entry_range(0);
+        assert_eq!(topic.selected_entry_positions(), [0]);
+    }
+    #[test]
+    fn test_move_entry_keeps_selection() {
+        let mut topic = Topic::new("Topic".into());
+        for title in ["a", "b", "c"] {
+            topic.entries.push(Entry::new_task(title.into()));
+        }
+        topic.task_sel = Some(1);
+        // a after c
+        topic.move_entry(0, 3);
+        let titles: Vec<_> = topic.entries.iter().map(|en| en.title.as_str()).collect();
+        assert_eq!(titles, ["b", "c", "a"]);
+        assert_eq!(topic.task_sel, Some(0));
+        let id = topic.entries[0].id;
+        topic.take_entry(id);
+        assert_eq!(topic.task_sel, None);
+    }
 }
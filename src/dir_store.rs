@@ -0,0 +1,698 @@
+use {
+    crate::{
+        app::{Settings, StoredFontData, TodoAppPersistent},
+        data::{Attachment, Entry, EntryKind, Id, Topic, assign_ids},
+        tree,
+    },
+    serde::{Deserialize, Serialize, de::DeserializeOwned},
+    std::{
+        collections::{BTreeMap, HashMap},
+        error::Error,
+        fmt::Write as _,
+        fs::File,
+        hash::{Hash as _, Hasher},
+        io::Write as _,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Marks a directory as a setodo store, and holds everything that isn't a topic or an entry
+const META_FILE: &str = "setodo.ron";
+/// Selection state. It changes all the time, so it's kept out of the way of version control.
+const STATE_FILE: &str = ".setodo-state.ron";
+/// Name and description of the topic whose directory it's in
+const TOPIC_FILE: &str = "_topic.md";
+/// Extension of the directory next to an entry file that holds its attachments
+const ATTACHMENTS_EXT: &str = "files";
+/// Renames and removals of a [`write`] that may not have finished, for [`read`] to finish
+const JOURNAL_FILE: &str = ".setodo-journal.ron";
+
+#[derive(Deserialize)]
+struct Meta {
+    #[serde(default)]
+    settings: Settings,
+    #[serde(default)]
+    stored_font_data: Option<StoredFontData>,
+}
+
+/// [`Meta`], for writing
+#[derive(Serialize)]
+struct MetaRef<'p> {
+    settings: &'p Settings,
+    stored_font_data: &'p Option<StoredFontData>,
+}
+
+/// What's left to do once all new files are written next to where they go.
+///
+/// Paths are relative to the store root.
+#[derive(Serialize, Deserialize, Default)]
+struct Journal {
+    /// Temporary file, and the path it replaces
+    renames: Vec<(PathBuf, PathBuf)>,
+    /// Files of topics, entries and attachments that no longer exist
+    removals: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct State {
+    topic_sel: Vec<usize>,
+    task_sel: HashMap<Id, usize>,
+}
+
+/// Whether `path` is a directory store, as opposed to a data file
+pub fn is_store(path: &Path) -> bool {
+    path.is_dir()
+}
+
+/// Read the directory store at `root`.
+///
+/// Topics and entries without an id (e.g. written by hand) get a new one.
+pub fn read(root: &Path) -> Result<TodoAppPersistent, Box<dyn Error>> {
+    let meta_path = root.join(META_FILE);
+    let meta_text = std::fs::read_to_string(&meta_path).map_err(|e| {
+        format!(
+            "'{}' is not a setodo directory store: can't read '{META_FILE}': {e}",
+            root.display()
+        )
+    })?;
+    finish_unfinished_write(root)?;
+    let meta: Meta = ron::from_str(&meta_text).map_err(|e| in_file(&meta_path, e))?;
+    let mut per = TodoAppPersistent {
+        topic_sel: Vec::new(),
+        topics: read_topics(root)?,
+        stored_font_data: meta.stored_font_data,
+        settings: meta.settings,
+    };
+    assign_ids(&mut per.topics);
+    // Losing the selection is no big deal, so errors are ignored
+    if let Ok(text) = std::fs::read_to_string(root.join(STATE_FILE))
+        && let Ok(state) = ron::from_str::<State>(&text)
+    {
+        apply_state(&mut per, &state);
+    }
+    Ok(per)
+}
+
+/// Write `per` to the directory store at `root`, creating it if needed.
+///
+/// The layout is
+/// - `setodo.ron`: settings and fonts
+/// - one directory per topic, with its name and description in `_topic.md`
+/// - one Markdown file per entry in its topic's directory, with the details in the front
+///   matter and the description as the body
+/// - the attachments of an entry as files in a `.files` directory next to it
+/// - subtasks in the front matter of their entry, in RON
+///
+/// Topics and entries are numbered to keep their order.
+/// Files that haven't changed aren't touched, and files of topics and entries that no longer
+/// exist are removed. Anything else in the directory is left alone.
+///
+/// Changed files are written to temporary files first. Only once all of them are written,
+/// they replace the old ones, and the old files are removed. If that is interrupted,
+/// the next [`read`] finishes it, so a crash can't leave both an old and a new copy of an
+/// entry behind.
+pub fn write(per: &TodoAppPersistent, root: &Path) -> Result<(), Box<dyn Error>> {
+    let mut files = BTreeMap::new();
+    let meta = MetaRef {
+        settings: &per.settings,
+        stored_font_data: &per.stored_font_data,
+    };
+    files.insert(
+        root.join(META_FILE),
+        to_ron(&meta)?.into_bytes().into_boxed_slice(),
+    );
+    collect_topic_files(&per.topics, root, &mut files)?;
+    std::fs::create_dir_all(root)?;
+    finish_unfinished_write(root)?;
+    // Before anything changes, so that the attachments of entries are found through the
+    // entry files as they are now
+    let mut existing = Vec::new();
+    list_store_files(root, true, &mut existing)?;
+    let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_owned();
+    let mut journal = Journal::default();
+    for (path, data) in &files {
+        if std::fs::read(path).is_ok_and(|old| *old == **data) {
+            continue;
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        journal.renames.push((relative(&tmp_path), relative(path)));
+    }
+    journal.removals = existing
+        .iter()
+        .filter(|path| !files.contains_key(*path))
+        .map(|path| relative(path))
+        .collect();
+    if !journal.renames.is_empty() || !journal.removals.is_empty() {
+        let journal_path = root.join(JOURNAL_FILE);
+        let mut file = File::create(&journal_path)?;
+        file.write_all(to_ron(&journal)?.as_bytes())?;
+        file.sync_all()?;
+        finish_journal(root, &journal)?;
+        std::fs::remove_file(journal_path)?;
+    }
+    let state = to_ron(&collect_state(per))?;
+    if let Err(e) = std::fs::write(root.join(STATE_FILE), state) {
+        eprintln!("Failed to save the selection: {e}");
+    }
+    Ok(())
+}
+
+/// Finish the last [`write`], if it was interrupted after writing its journal.
+///
+/// Without a journal, or with one that was only partly written, the store is still as it
+/// was before that write.
+fn finish_unfinished_write(root: &Path) -> Result<(), Box<dyn Error>> {
+    let journal_path = root.join(JOURNAL_FILE);
+    let Ok(text) = std::fs::read_to_string(&journal_path) else {
+        return Ok(());
+    };
+    if let Ok(journal) = ron::from_str::<Journal>(&text) {
+        finish_journal(root, &journal)?;
+    }
+    std::fs::remove_file(journal_path)?;
+    Ok(())
+}
+
+/// Do the renames and removals of `journal`. Whatever was already done is skipped.
+fn finish_journal(root: &Path, journal: &Journal) -> std::io::Result<()> {
+    for (tmp_path, path) in &journal.renames {
+        let tmp_path = root.join(tmp_path);
+        if tmp_path.exists() {
+            std::fs::rename(tmp_path, root.join(path))?;
+        }
+    }
+    for path in &journal.removals {
+        let path = root.join(path);
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        remove_empty_dirs(path.parent(), root);
+    }
+    Ok(())
+}
+
+/// Feed the contents of the store files under `root` to `hasher`
+pub fn hash(root: &Path, hasher: &mut impl Hasher) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    list_store_files(root, true, &mut files)?;
+    for path in files {
+        path.hash(hasher);
+        std::fs::read(&path)?.hash(hasher);
+    }
+    Ok(())
+}
+
+fn to_ron(value: &impl Serialize) -> Result<String, ron::Error> {
+    ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+}
+
+fn in_file(path: &Path, e: impl std::fmt::Display) -> String {
+    format!("'{}': {e}", path.display())
+}
+
+/// Existing files that belong to the store, in a stable order.
+///
+/// Only looks into directories that are topics, and skips hidden files. Of the other files,
+/// only the ones written by [`write`] count: topic and entry files, and the attachments
+/// named in the entry files.
+fn list_store_files(dir: &Path, is_root: bool, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for (name, path) in sorted_dir_entries(dir)? {
+        if path.is_dir() {
+            if path.join(TOPIC_FILE).is_file() {
+                list_store_files(&path, false, out)?;
+            }
+        } else if is_root {
+            if name == META_FILE {
+                out.push(path);
+            }
+        } else if name == TOPIC_FILE {
+            out.push(path);
+        } else if is_entry_file_name(&name) {
+            let attachment_dir = path.with_extension(ATTACHMENTS_EXT);
+            let attachments = attachment_names_in(&path);
+            out.push(path);
+            for name in attachments {
+                let file = attachment_dir.join(name);
+                if file.is_file() {
+                    out.push(file);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` is the name of an entry file, like the ones [`file_stem`] makes.
+///
+/// Other Markdown files, like a README, are the user's own.
+fn is_entry_file_name(name: &str) -> bool {
+    name.strip_suffix(".md")
+        .and_then(|stem| stem.split_once(' '))
+        .is_some_and(|(num, rest)| {
+            num.len() >= 3
+                && num.bytes().all(|b| b.is_ascii_digit())
+                && !rest.is_empty()
+                && !rest.contains('.')
+        })
+}
+
+/// On-disk names of the attachments listed in the entry file at `path`.
+///
+/// Empty if it can't be read, so that nothing is mistaken for a stale attachment.
+fn attachment_names_in(path: &Path) -> Vec<String> {
+    let Ok(text) = read_md(path) else {
+        return Vec::new();
+    };
+    let filenames: Option<Vec<PathBuf>> = parse_front_matter(&text)
+        .0
+        .into_iter()
+        .filter(|(key, _)| *key == "attachment")
+        .map(|(_, value)| ron::from_str(value).ok())
+        .collect();
+    filenames.map_or_else(Vec::new, |filenames| {
+        attachment_file_names(filenames.iter())
+    })
+}
+
+/// The non-hidden entries of `dir`, in the order of their number prefix, then name
+fn sorted_dir_entries(dir: &Path) -> std::io::Result<Vec<(String, PathBuf)>> {
+    let mut entries = Vec::new();
+    for dir_entry in std::fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let name = dir_entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with('.') {
+            entries.push((name, dir_entry.path()));
+        }
+    }
+    entries.sort_by_cached_key(|(name, _)| (split_number(name).0, name.clone()));
+    Ok(entries)
+}
+
+/// Split `"012 Name"` into `12` and `"Name"`. Names without a number sort last.
+fn split_number(name: &str) -> (u64, &str) {
+    name.split_once(' ')
+        .and_then(|(num, rest)| Some((num.parse().ok()?, rest)))
+        .unwrap_or((u64::MAX, name))
+}
+
+/// Remove `dir` and its parents up to `root` for as long as they're empty
+fn remove_empty_dirs(mut dir: Option<&Path>, root: &Path) {
+    while let Some(path) = dir
+        && path != root
+        && std::fs::remove_dir(path).is_ok()
+    {
+        dir = path.parent();
+    }
+}
+
+/// A file or directory name for something called `name` at position `idx`.
+///
+/// Characters that could cause trouble on some file system are replaced, and so are dots,
+/// so topic directories can't be mistaken for entry files or attachment directories.
+fn file_stem(idx: usize, name: &str) -> String {
+    let mut clean: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(48)
+        .collect();
+    clean = String::from(clean.trim());
+    if clean.is_empty() {
+        clean.push_str("untitled");
+    }
+    format!("{idx:03} {clean}")
+}
+
+/// On-disk names of attachments. Usually their filename, but those have to be unique.
+fn attachment_file_names(filenames: impl Iterator<Item = impl AsRef<Path>>) -> Vec<String> {
+    let names: Vec<String> = filenames
+        .map(|filename| {
+            filename
+                .as_ref()
+                .file_name()
+                .map_or_else(|| "attachment".into(), |n| n.to_string_lossy().into_owned())
+        })
+        .collect();
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            if names.iter().filter(|other| *other == name).count() > 1 {
+                format!("{i}_{name}")
+            } else {
+                name.clone()
+            }
+        })
+        .collect()
+}
+
+type Files = BTreeMap<PathBuf, Box<[u8]>>;
+
+fn collect_topic_files(topics: &[Topic], dir: &Path, files: &mut Files) -> Result<(), ron::Error> {
+    for (i, topic) in topics.iter().enumerate() {
+        let topic_dir = dir.join(file_stem(i, &topic.name));
+        let mut text = String::from("---\n");
+        front_matter_field(&mut text, "name", &topic.name)?;
+        let _ = writeln!(text, "id: {}", topic.id);
+        text.push_str("---\n");
+        push_body(&mut text, &topic.desc);
+        files.insert(topic_dir.join(TOPIC_FILE), text.into_bytes().into());
+        for (i, entry) in topic.entries.iter().enumerate() {
+            let stem = file_stem(i, &entry.title);
+            files.insert(
+                topic_dir.join(format!("{stem}.md")),
+                entry_file_text(entry)?.into_bytes().into(),
+            );
+            let attachment_dir = topic_dir.join(format!("{stem}.{ATTACHMENTS_EXT}"));
+            for (att, name) in entry.attachments.iter().zip(attachment_file_names(
+                entry.attachments.iter().map(|att| att.filename.as_path()),
+            )) {
+                files.insert(
+                    attachment_dir.join(name),
+                    att.data.iter().copied().collect(),
+                );
+            }
+        }
+        collect_topic_files(&topic.children, &topic_dir, files)?;
+    }
+    Ok(())
+}
+
+fn entry_file_text(entry: &Entry) -> Result<String, ron::Error> {
+    let mut text = String::from("---\n");
+    front_matter_field(&mut text, "title", &entry.title)?;
+    let _ = writeln!(text, "id: {}", entry.id);
+    if entry.kind != EntryKind::Task {
+        front_matter_field(&mut text, "kind", &entry.kind)?;
+    }
+    if entry.done {
+        front_matter_field(&mut text, "done", &entry.done)?;
+    }
+    if let Some(due) = &entry.due {
+        front_matter_field(&mut text, "due", due)?;
+    }
+    if let Some(scheduled) = &entry.scheduled {
+        front_matter_field(&mut text, "scheduled", scheduled)?;
+    }
+    if let Some(recurrence) = &entry.recurrence {
+        front_matter_field(&mut text, "recurrence", recurrence)?;
+    }
+    if !entry.completion_log.is_empty() {
+        front_matter_field(&mut text, "completed", &entry.completion_log)?;
+    }
+    for att in &entry.attachments {
+        front_matter_field(&mut text, "attachment", &att.filename)?;
+    }
+    if !entry.children.is_empty() {
+        front_matter_field(&mut text, "subtasks", &entry.children)?;
+    }
+    text.push_str("---\n");
+    push_body(&mut text, &entry.desc);
+    Ok(text)
+}
+
+/// A `key: value` line, with the value in RON, so any string survives
+fn front_matter_field(
+    text: &mut String,
+    key: &str,
+    value: &(impl Serialize + ?Sized),
+) -> Result<(), ron::Error> {
+    let _ = writeln!(text, "{key}: {}", ron::to_string(value)?);
+    Ok(())
+}
+
+/// Descriptions get a final newline, which is taken off again when reading
+fn push_body(text: &mut String, body: &str) {
+    if !body.is_empty() {
+        text.push_str(body);
+        text.push('\n');
+    }
+}
+
+/// Split a Markdown file into its front matter fields and its body
+fn parse_front_matter(text: &str) -> (Vec<(&str, &str)>, &str) {
+    let Some(rest) = text.strip_prefix("---\n") else {
+        return (Vec::new(), text.strip_suffix('\n').unwrap_or(text));
+    };
+    let (front, body) = if let Some(front) = rest.strip_prefix("---\n") {
+        ("", front)
+    } else if let Some((front, body)) = rest.split_once("\n---\n") {
+        (front, body)
+    } else {
+        (rest.strip_suffix("\n---").unwrap_or(rest), "")
+    };
+    let fields = front
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    (fields, body.strip_suffix('\n').unwrap_or(body))
+}
+
+/// Read a Markdown file, with Windows line endings turned into `\n`
+fn read_md(path: &Path) -> Result<String, Box<dyn Error>> {
+    Ok(std::fs::read_to_string(path)
+        .map_err(|e| in_file(path, e))?
+        .replace("\r\n", "\n"))
+}
+
+fn parse_value<T: DeserializeOwned>(path: &Path, key: &str, value: &str) -> Result<T, String> {
+    ron::from_str(value).map_err(|e| in_file(path, format!("{key}: {e}")))
+}
+
+fn parse_id(path: &Path, value: &str) -> Result<Id, String> {
+    value.parse().map_err(|e| in_file(path, format!("id: {e}")))
+}
+
+fn read_topics(dir: &Path) -> Result<Vec<Topic>, Box<dyn Error>> {
+    let mut topics = Vec::new();
+    for (name, path) in sorted_dir_entries(dir)? {
+        if path.join(TOPIC_FILE).is_file() {
+            topics.push(read_topic(&path, split_number(&name).1)?);
+        }
+    }
+    Ok(topics)
+}
+
+fn read_topic(dir: &Path, dir_name: &str) -> Result<Topic, Box<dyn Error>> {
+    let topic_path = dir.join(TOPIC_FILE);
+    let text = read_md(&topic_path)?;
+    let (fields, body) = parse_front_matter(&text);
+    let mut topic = Topic {
+        desc: body.to_owned(),
+        id: Id::UNASSIGNED,
+        ..Topic::new(dir_name.to_owned())
+    };
+    for (key, value) in fields {
+        match key {
+            "name" => topic.name = parse_value(&topic_path, key, value)?,
+            "id" => topic.id = parse_id(&topic_path, value)?,
+            _ => {}
+        }
+    }
+    for (name, path) in sorted_dir_entries(dir)? {
+        if is_entry_file_name(&name) && path.is_file() {
+            topic.entries.push(read_entry(&path)?);
+        }
+    }
+    topic.children = read_topics(dir)?;
+    Ok(topic)
+}
+
+fn read_entry(path: &Path) -> Result<Entry, Box<dyn Error>> {
+    let text = read_md(path)?;
+    let (fields, body) = parse_front_matter(&text);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut entry = Entry {
+        desc: body.to_owned(),
+        id: Id::UNASSIGNED,
+        ..Entry::new_task(split_number(&stem).1.to_owned())
+    };
+    let mut attachment_names: Vec<PathBuf> = Vec::new();
+    for (key, value) in fields {
+        match key {
+            "title" => entry.title = parse_value(path, key, value)?,
+            "id" => entry.id = parse_id(path, value)?,
+            "kind" => entry.kind = parse_value(path, key, value)?,
+            "done" => entry.done = parse_value(path, key, value)?,
+            "due" => entry.due = Some(parse_value(path, key, value)?),
+            "scheduled" => entry.scheduled = Some(parse_value(path, key, value)?),
+            "recurrence" => entry.recurrence = Some(parse_value(path, key, value)?),
+            "completed" => entry.completion_log = parse_value(path, key, value)?,
+            "attachment" => attachment_names.push(parse_value(path, key, value)?),
+            "subtasks" => entry.children = parse_value(path, key, value)?,
+            _ => {}
+        }
+    }
+    entry.attachments = attachment_names
+        .into_iter()
+        .map(|filename| Attachment {
+            filename,
+            data: Vec::new().into(),
+        })
+        .collect();
+    strip_attachment_dirs(&mut entry);
+    let attachment_dir = path.with_extension(ATTACHMENTS_EXT);
+    let file_names =
+        attachment_file_names(entry.attachments.iter().map(|att| att.filename.as_path()));
+    for (att, name) in entry.attachments.iter_mut().zip(file_names) {
+        let att_path = attachment_dir.join(name);
+        att.data = std::fs::read(&att_path)
+            .map_err(|e| in_file(&att_path, e))?
+            .into();
+    }
+    Ok(entry)
+}
+
+/// Reduce the attachment filenames of `entry` and its subtasks to their last component.
+///
+/// Entry files may have been edited by hand, and a filename with directories in it would
+/// make exporting the attachment write outside of the export directory.
+fn strip_attachment_dirs(entry: &mut Entry) {
+    for att in &mut entry.attachments {
+        att.filename = att
+            .filename
+            .file_name()
+            .map_or_else(|| "attachment".into(), PathBuf::from);
+    }
+    for child in &mut entry.children {
+        strip_attachment_dirs(child);
+    }
+}
+
+fn collect_state(per: &TodoAppPersistent) -> State {
+    fn inner(topics: &[Topic], task_sel: &mut HashMap<Id, usize>) {
+        for topic in topics {
+            if let Some(sel) = topic.task_sel {
+                task_sel.insert(topic.id, sel);
+            }
+            inner(&topic.children, task_sel);
+        }
+    }
+    let mut state = State {
+        topic_sel: per.topic_sel.clone(),
+        task_sel: HashMap::new(),
+    };
+    inner(&per.topics, &mut state.task_sel);
+    state
+}
+
+fn apply_state(per: &mut TodoAppPersistent, state: &State) {
+    fn inner(topics: &mut [Topic], task_sel: &HashMap<Id, usize>) {
+        for topic in topics {
+            topic.task_sel = task_sel
+                .get(&topic.id)
+                .copied()
+                .filter(|&sel| sel < topic.entries.len());
+            inner(&mut topic.children, task_sel);
+        }
+    }
+    if tree::get(&per.topics, &state.topic_sel).is_some() {
+        per.topic_sel.clone_from(&state.topic_sel);
+    }
+    inner(&mut per.topics, &state.task_sel);
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{ATTACHMENTS_EXT, JOURNAL_FILE, Journal, file_stem, read, to_ron, write},
+        crate::{
+            app::TodoAppPersistent,
+            data::{Attachment, Entry, EntryKind, Recurrence, Topic, same_content},
+        },
+        chrono::NaiveDate,
+    };
+
+    #[test]
+    fn test_dir_store_round_trip() {
+        let root = std::env::temp_dir().join(format!("setodo-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let mut per = TodoAppPersistent::default();
+        let mut topic = Topic::new("Work: 2024/Q1".into());
+        topic.desc = "Things\n\nto do\n".into();
+        let mut task = Entry::new_task("Write \"the\" report.\n(really)".into());
+        task.due = NaiveDate::from_ymd_opt(2024, 3, 20);
+        task.recurrence = Some(Recurrence::Days(7));
+        task.completion_log = vec![NaiveDate::from_ymd_opt(2024, 3, 13).unwrap()];
+        for data in [&b"one"[..], b"two"] {
+            task.attachments.push(Attachment {
+                filename: "same.txt".into(),
+                data: data.into(),
+            });
+        }
+        task.children
+            .push(Entry::new_task("Draft: part 1\nand 2".into()));
+        topic.entries.push(task);
+        let mut info = Entry::new_task(String::new());
+        info.kind = EntryKind::Info;
+        info.desc = "---\nnot front matter".into();
+        topic.entries.push(info);
+        topic.children.push(Topic::new("Sub".into()));
+        per.topics.push(topic);
+        per.topics.push(Topic::new("Home".into()));
+        per.topic_sel = vec![0, 0];
+        write(&per, &root).unwrap();
+        // Things of the user's, which must survive, and not be taken for entries or attachments
+        let topic_dir = root.join(file_stem(0, &per.topics[0].name));
+        let attachment_dir = topic_dir.join(format!(
+            "{}.{ATTACHMENTS_EXT}",
+            file_stem(0, &per.topics[0].entries[0].title)
+        ));
+        std::fs::write(root.join("README.md"), "hi").unwrap();
+        std::fs::write(topic_dir.join("NOTES.md"), "hi").unwrap();
+        std::fs::write(attachment_dir.join("mine.txt"), "hi").unwrap();
+        let read_back = read(&root).unwrap();
+        assert!(same_content(&per.topics, &read_back.topics));
+        assert_eq!(read_back.topic_sel, per.topic_sel);
+        // Removed things are removed from disk too
+        per.topics[0].entries.remove(0);
+        per.topics.remove(1);
+        write(&per, &root).unwrap();
+        let read_back = read(&root).unwrap();
+        assert!(same_content(&per.topics, &read_back.topics));
+        assert!(root.join("README.md").exists());
+        assert!(topic_dir.join("NOTES.md").exists());
+        assert_eq!(std::fs::read_dir(&attachment_dir).unwrap().count(), 1);
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 4);
+        // A write that was interrupted after moving an entry to a new file, but before
+        // removing the old one, is finished by the next read
+        let old_file = topic_dir.join(format!("{}.md", file_stem(0, "")));
+        let new_file = topic_dir.join(format!("{}.md", file_stem(1, "")));
+        std::fs::copy(
+            &old_file,
+            topic_dir.join(format!("{}.md.tmp", file_stem(1, ""))),
+        )
+        .unwrap();
+        let journal = Journal {
+            renames: vec![(
+                new_file
+                    .with_extension("md.tmp")
+                    .strip_prefix(&root)
+                    .unwrap()
+                    .into(),
+                new_file.strip_prefix(&root).unwrap().into(),
+            )],
+            removals: vec![old_file.strip_prefix(&root).unwrap().into()],
+        };
+        std::fs::write(root.join(JOURNAL_FILE), to_ron(&journal).unwrap()).unwrap();
+        let read_back = read(&root).unwrap();
+        assert!(same_content(&per.topics, &read_back.topics));
+        assert!(new_file.exists() && !old_file.exists());
+        assert!(!root.join(JOURNAL_FILE).exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
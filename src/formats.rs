@@ -0,0 +1,154 @@
+use {
+    crate::{
+        app::{Settings, TodoAppPersistent},
+        data::Topic,
+        tree,
+    },
+    std::{error::Error, fmt::Display, path::Path},
+};
+
+pub mod ical;
+pub mod markdown;
+pub mod todotxt;
+
+/// Text formats topics can be exported to
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Markdown,
+    TodoTxt,
+    ICalendar,
+}
+
+impl ExportFormat {
+    pub const ALL: [Self; 3] = [Self::Markdown, Self::TodoTxt, Self::ICalendar];
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Markdown => "Markdown",
+            Self::TodoTxt => "todo.txt",
+            Self::ICalendar => "iCalendar",
+        }
+    }
+
+    /// Write `topics` (and their subtopics) to the file at `path`
+    pub fn export(
+        self,
+        topics: &[Topic],
+        path: &Path,
+        settings: &Settings,
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Markdown => markdown::export(topics, path, settings.export_attachments),
+            Self::TodoTxt => Ok(std::fs::write(path, todotxt::export(topics))?),
+            Self::ICalendar => Ok(std::fs::write(path, ical::export(topics))?),
+        }
+    }
+}
+
+/// Formats topics and entries can be imported from
+#[derive(Clone, Copy)]
+pub enum ImportFormat {
+    Markdown,
+    TodoTxt,
+    ICalendar,
+}
+
+impl ImportFormat {
+    pub const ALL: [Self; 3] = [Self::Markdown, Self::TodoTxt, Self::ICalendar];
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Markdown => "Markdown",
+            Self::TodoTxt => "todo.txt",
+            Self::ICalendar => "iCalendar",
+        }
+    }
+
+    /// Import the file at `path` into `per`.
+    ///
+    /// Things without a place of their own go into the selected topic, or into a new top level
+    /// topic named after the file if there is no selection.
+    pub fn import(
+        self,
+        path: &Path,
+        per: &mut TodoAppPersistent,
+    ) -> Result<ImportStats, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let fallback_name = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        match self {
+            Self::Markdown => {
+                let mut imported = markdown::parse(&text);
+                let stats = ImportStats::count(&imported);
+                let topic = selected_or_new_topic(per, &fallback_name);
+                if !imported.desc.is_empty() {
+                    if !topic.desc.is_empty() {
+                        topic.desc.push_str("\n\n");
+                    }
+                    topic.desc.push_str(&imported.desc);
+                }
+                topic.entries.append(&mut imported.entries);
+                topic.children.append(&mut imported.children);
+                Ok(stats)
+            }
+            Self::TodoTxt => Ok(todotxt::import(&text, per, &fallback_name)),
+            Self::ICalendar => Ok(ical::import(&text, per, &fallback_name)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ImportStats {
+    pub topics_added: usize,
+    pub entries_added: usize,
+    pub entries_updated: usize,
+}
+
+impl ImportStats {
+    /// Stats for adding the topics below `topic`, and the entries in and below it
+    fn count(topic: &Topic) -> Self {
+        let mut stats = Self {
+            topics_added: topic.children.len(),
+            entries_added: topic.entries.len(),
+            entries_updated: 0,
+        };
+        for child in &topic.children {
+            let child_stats = Self::count(child);
+            stats.topics_added += child_stats.topics_added;
+            stats.entries_added += child_stats.entries_added;
+        }
+        stats
+    }
+}
+
+impl Display for ImportStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Added {} topic(s) and {} entries",
+            self.topics_added, self.entries_added
+        )?;
+        if self.entries_updated != 0 {
+            write!(f, ", updated {} entries", self.entries_updated)?;
+        }
+        Ok(())
+    }
+}
+
+/// The selected topic, or a new top level topic called `name` if there is no selection
+fn selected_or_new_topic<'p>(per: &'p mut TodoAppPersistent, name: &str) -> &'p mut Topic {
+    if tree::get(&per.topics, &per.topic_sel).is_none() {
+        per.topics.push(Topic::new(name.to_owned()));
+        per.topic_sel = vec![per.topics.len() - 1];
+    }
+    tree::get_mut(&mut per.topics, &per.topic_sel).unwrap()
+}
+
+/// Which topics to export
+#[derive(Clone, Copy)]
+pub enum ExportScope {
+    /// The selected topic and its subtopics
+    SelectedTopic,
+    All,
+}
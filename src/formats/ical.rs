@@ -0,0 +1,378 @@
+use {
+    super::{ImportStats, selected_or_new_topic},
+    crate::{
+        app::TodoAppPersistent,
+        data::{Entry, EntryKind, Id, Recurrence, Topic, find_entry_mut, fnv1a},
+    },
+    chrono::{Datelike as _, NaiveDate},
+};
+
+/// Suffix of the UIDs of exported entries. The part before it is the entry id.
+const UID_SUFFIX: &str = "@setodo";
+
+/// Write the tasks in `topics` as VTODO components of an iCalendar document.
+///
+/// The topic path becomes the category, and the entry id the UID, so importing the file
+/// updates the entries instead of duplicating them. Subtasks are linked to their parent task
+/// by a `RELATED-TO` property.
+pub fn export(topics: &[Topic]) -> String {
+    let mut out = String::new();
+    write_line(&mut out, "BEGIN:VCALENDAR");
+    write_line(&mut out, "VERSION:2.0");
+    write_line(&mut out, "PRODID:-//setodo//setodo//EN");
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    write_topics(&mut out, topics, "", &stamp);
+    write_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+fn write_topics(out: &mut String, topics: &[Topic], parent: &str, stamp: &str) {
+    for topic in topics {
+        let path = if parent.is_empty() {
+            topic.name.clone()
+        } else {
+            format!("{parent}/{}", topic.name)
+        };
+        write_entries(out, &topic.entries, &path, None, stamp);
+        write_topics(out, &topic.children, &path, stamp);
+    }
+}
+
+fn write_entries(
+    out: &mut String,
+    entries: &[Entry],
+    category: &str,
+    parent: Option<Id>,
+    stamp: &str,
+) {
+    for entry in entries {
+        if entry.kind == EntryKind::Task {
+            write_vtodo(out, entry, category, parent, stamp);
+            write_entries(out, &entry.children, category, Some(entry.id), stamp);
+        }
+    }
+}
+
+fn write_vtodo(out: &mut String, entry: &Entry, category: &str, parent: Option<Id>, stamp: &str) {
+    write_line(out, "BEGIN:VTODO");
+    write_line(out, &format!("UID:{}{UID_SUFFIX}", entry.id));
+    write_line(out, &format!("DTSTAMP:{stamp}"));
+    if let Some(parent) = parent {
+        write_line(out, &format!("RELATED-TO:{parent}{UID_SUFFIX}"));
+    }
+    write_line(out, &format!("SUMMARY:{}", escape(&entry.title)));
+    if !entry.desc.is_empty() {
+        write_line(out, &format!("DESCRIPTION:{}", escape(&entry.desc)));
+    }
+    write_line(out, &format!("CATEGORIES:{}", escape(category)));
+    if let Some(scheduled) = entry.scheduled {
+        write_line(
+            out,
+            &format!("DTSTART;VALUE=DATE:{}", scheduled.format("%Y%m%d")),
+        );
+    }
+    if let Some(due) = entry.due {
+        write_line(out, &format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")));
+    }
+    if let Some(recurrence) = entry.recurrence {
+        let rule = match recurrence {
+            Recurrence::Days(n) => format!("FREQ=DAILY;INTERVAL={n}"),
+            Recurrence::Weekdays => "FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR".into(),
+            Recurrence::MonthDay(day) => format!("FREQ=MONTHLY;BYMONTHDAY={day}"),
+        };
+        write_line(out, &format!("RRULE:{rule}"));
+    }
+    let status = if entry.done {
+        "COMPLETED"
+    } else {
+        "NEEDS-ACTION"
+    };
+    write_line(out, &format!("STATUS:{status}"));
+    write_line(out, "END:VTODO");
+}
+
+/// Write a content line, folded to lines of at most 75 bytes as the spec requires
+fn write_line(out: &mut String, line: &str) {
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// The properties of a VTODO that we understand
+#[derive(Default)]
+struct Vtodo {
+    uid: Option<String>,
+    /// UID of the parent VTODO
+    parent_uid: Option<String>,
+    summary: String,
+    /// `None` if there's no DESCRIPTION, which leaves the description of an existing entry
+    description: Option<String>,
+    done: bool,
+    due: Option<NaiveDate>,
+    scheduled: Option<NaiveDate>,
+    recurrence: Option<Recurrence>,
+}
+
+fn parse_vtodos(text: &str) -> Vec<Vtodo> {
+    // Undo line folding
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(cont), Some(last)) => last.push_str(cont),
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    let mut todos = Vec::new();
+    let mut current: Option<Vtodo> = None;
+    // Depth of components nested in the VTODO, like VALARM
+    let mut nested = 0;
+    for line in &lines {
+        let Some((name_params, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name_params
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+        match (name.as_str(), &mut current) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VTODO") => {
+                current = Some(Vtodo::default());
+            }
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(_)) => todos.extend(current.take()),
+            (_, Some(todo)) if nested == 0 => match name.as_str() {
+                "UID" => todo.uid = Some(value.to_owned()),
+                // Without a RELTYPE, the relationship is to the parent
+                "RELATED-TO" if is_parent_relation(name_params) => {
+                    todo.parent_uid = Some(value.to_owned());
+                }
+                "SUMMARY" => todo.summary = unescape(value),
+                "DESCRIPTION" => todo.description = Some(unescape(value)),
+                "STATUS" => todo.done = value.eq_ignore_ascii_case("COMPLETED"),
+                "DUE" => todo.due = parse_date(value),
+                "DTSTART" => todo.scheduled = parse_date(value),
+                "RRULE" => todo.recurrence = parse_rrule(value, todo.due),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    todos
+}
+
+/// Whether the parameters of a RELATED-TO property make it point to the parent
+fn is_parent_relation(name_params: &str) -> bool {
+    name_params.split(';').skip(1).all(|param| {
+        param.split_once('=').is_none_or(|(key, value)| {
+            !key.eq_ignore_ascii_case("RELTYPE") || value.eq_ignore_ascii_case("PARENT")
+        })
+    })
+}
+
+/// Parse a DATE or DATE-TIME value. The time is ignored.
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+/// Parse the recurrence rules we can represent
+fn parse_rrule(value: &str, due: Option<NaiveDate>) -> Option<Recurrence> {
+    let mut freq = None;
+    let mut interval: u32 = 1;
+    let mut by_day = None;
+    let mut by_month_day = None;
+    for part in value.split(';') {
+        match part.split_once('=')? {
+            ("FREQ", f) => freq = Some(f),
+            ("INTERVAL", n) => interval = n.parse().ok()?,
+            ("BYDAY", days) => by_day = Some(days),
+            ("BYMONTHDAY", day) => by_month_day = Some(day.parse().ok()?),
+            _ => {}
+        }
+    }
+    match (freq?, by_day) {
+        ("DAILY", None) => Some(Recurrence::Days(interval)),
+        ("WEEKLY", None) => interval.checked_mul(7).map(Recurrence::Days),
+        ("WEEKLY", Some("MO,TU,WE,TH,FR")) if interval == 1 => Some(Recurrence::Weekdays),
+        ("MONTHLY", None) if interval == 1 => Some(Recurrence::MonthDay(
+            by_month_day.or_else(|| due.map(|due| due.day()))?,
+        )),
+        _ => None,
+    }
+}
+
+/// The entry id for a VTODO UID.
+///
+/// UIDs of exported entries contain the id. Others are hashed, so importing the same
+/// file again still updates the entries it created the first time.
+fn uid_to_id(uid: &str) -> Id {
+    if let Some(id) = uid.strip_suffix(UID_SUFFIX).and_then(|id| id.parse().ok()) {
+        return id;
+    }
+    Id(fnv1a(uid.as_bytes()).max(1))
+}
+
+/// Import the VTODOs of an iCalendar document into `per`.
+///
+/// VTODOs matching an existing entry by UID update it, the others are added to the selected
+/// topic, or a topic called `fallback_name` if there is no selection. New VTODOs related to
+/// an existing task become its subtasks.
+pub fn import(text: &str, per: &mut TodoAppPersistent, fallback_name: &str) -> ImportStats {
+    let mut stats = ImportStats::default();
+    let today = chrono::Local::now().date_naive();
+    for todo in parse_vtodos(text) {
+        let id = todo.uid.as_deref().map_or_else(Id::new, uid_to_id);
+        let existing = find_entry_mut(&mut per.topics, id);
+        let is_new = existing.is_none();
+        let mut new_entry = Entry::new_task(String::new());
+        let entry = existing.unwrap_or(&mut new_entry);
+        let completes_recurring = todo.done && todo.recurrence.is_some();
+        if completes_recurring && !is_new && todo.due != entry.due {
+            // This occurrence was already rolled forward by an earlier import
+            continue;
+        }
+        let before = entry.clone();
+        entry.id = id;
+        entry.title = todo.summary;
+        if let Some(desc) = todo.description {
+            entry.desc = desc;
+        }
+        entry.done = todo.done;
+        entry.due = todo.due;
+        entry.scheduled = todo.scheduled;
+        entry.recurrence = todo.recurrence;
+        if completes_recurring {
+            entry.roll_forward(today);
+        }
+        if is_new {
+            let parent = todo
+                .parent_uid
+                .and_then(|uid| find_entry_mut(&mut per.topics, uid_to_id(&uid)));
+            match parent {
+                Some(parent) => parent.children.push(new_entry),
+                None => selected_or_new_topic(per, fallback_name)
+                    .entries
+                    .push(new_entry),
+            }
+            stats.entries_added += 1;
+        } else if *entry != before {
+            stats.entries_updated += 1;
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{export, import, parse_rrule},
+        crate::{
+            app::TodoAppPersistent,
+            data::{Entry, Recurrence, Topic},
+        },
+        chrono::NaiveDate,
+    };
+
+    #[test]
+    fn test_ical_round_trip() {
+        let mut per = TodoAppPersistent::default();
+        let mut topic = Topic::new("Work".into());
+        let mut task = Entry::new_task("Report, part 1; draft".into());
+        task.desc = "Long description that goes on and on, well past the point where the \
+                     line has to be folded\nSecond line"
+            .into();
+        task.due = NaiveDate::from_ymd_opt(2024, 3, 20);
+        task.recurrence = Some(Recurrence::Weekdays);
+        topic.entries.push(task.clone());
+        per.topics.push(topic);
+        per.topic_sel = vec![0];
+        let text = export(&per.topics);
+        assert!(text.lines().all(|line| line.len() <= 76));
+        assert!(text.contains("CATEGORIES:Work\r\n"));
+        let stats = import(&text, &mut per, "ics");
+        assert_eq!((stats.entries_added, stats.entries_updated), (0, 0));
+        // Edited in a client that leaves out the description
+        let no_desc: String = text
+            .split_inclusive("\r\n")
+            .filter(|line| !line.starts_with("DESCRIPTION") && !line.starts_with(' '))
+            .collect();
+        import(&no_desc, &mut per, "ics");
+        assert_eq!(per.topics[0].entries[0].desc, task.desc);
+        // Completed in the calendar client, and a foreign VTODO added
+        let text = text.replace("NEEDS-ACTION", "COMPLETED").replace(
+            "END:VCALENDAR",
+            "BEGIN:VTODO\r\nUID:abc@example.com\r\nSUMMARY:Call Bob\r\nEND:VTODO\r\n\
+             END:VCALENDAR",
+        );
+        for _ in 0..2 {
+            import(&text, &mut per, "ics");
+        }
+        let entries = &per.topics[0].entries;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title, task.title);
+        assert_eq!(entries[0].desc, task.desc);
+        assert_eq!(entries[0].recurrence, task.recurrence);
+        // Recurring, so it was rolled forward once, and the second import was outdated
+        assert_eq!(entries[0].completion_log.len(), 1);
+        assert_eq!(entries[1].title, "Call Bob");
+        // Too many weeks to count in days
+        assert_eq!(parse_rrule("FREQ=WEEKLY;INTERVAL=1000000000", None), None);
+    }
+
+    #[test]
+    fn test_ical_subtasks() {
+        let mut topic = Topic::new("Work".into());
+        let mut task = Entry::new_task("Release".into());
+        task.children.push(Entry::new_task("Write notes".into()));
+        topic.entries.push(task);
+        let text = export(&[topic]);
+        assert!(text.contains("RELATED-TO:"));
+        let mut per = TodoAppPersistent::default();
+        let stats = import(&text, &mut per, "ics");
+        assert_eq!(stats.entries_added, 2);
+        let entries = &per.topics[0].entries;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].children[0].title, "Write notes");
+    }
+}
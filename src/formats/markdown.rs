@@ -0,0 +1,533 @@
+use {
+    crate::data::{Attachment, Entry, EntryKind, Id, Topic},
+    pulldown_cmark::{Event, Options, Parser, Tag, TagEnd},
+    std::{error::Error, fmt::Write as _, ops::Range, path::Path},
+};
+
+/// Write `topics` to a Markdown document at `path`.
+///
+/// If `with_attachments` is set, attachments are written to a `<name>_files` directory next
+/// to the document, and linked from their entries.
+pub fn export(topics: &[Topic], path: &Path, with_attachments: bool) -> Result<(), Box<dyn Error>> {
+    let mut files_dir_name = path.file_stem().unwrap_or_default().to_owned();
+    files_dir_name.push("_files");
+    let files_dir = path.with_file_name(&files_dir_name);
+    let mut io_result = Ok(());
+    let doc = to_markdown(topics, |entry, attachment| {
+        if !with_attachments {
+            return None;
+        }
+        // Only the last component, so the file can't end up outside of the directory
+        let name = attachment
+            .filename
+            .file_name()
+            .unwrap_or_else(|| "attachment".as_ref());
+        let rel_path = Path::new(&files_dir_name)
+            .join(entry.id.to_string())
+            .join(name);
+        let abs_path = files_dir.join(entry.id.to_string()).join(name);
+        let result = std::fs::create_dir_all(abs_path.parent().unwrap())
+            .and_then(|()| std::fs::write(&abs_path, &attachment.data));
+        if let Err(e) = result {
+            io_result = Err(e);
+            return None;
+        }
+        // Markdown wants forward slashes, even on Windows
+        Some(rel_path.to_string_lossy().replace('\\', "/"))
+    });
+    io_result?;
+    std::fs::write(path, doc)?;
+    Ok(())
+}
+
+/// Render topics as a Markdown document.
+///
+/// Topic nesting becomes heading levels, tasks become task list items, and info entries
+/// plain list items, with their subtasks as nested list items. `attachment_link` decides
+/// where attachments link to, if anywhere.
+pub fn to_markdown(
+    topics: &[Topic],
+    mut attachment_link: impl FnMut(&Entry, &Attachment) -> Option<String>,
+) -> String {
+    let mut out = String::new();
+    write_topics(&mut out, topics, 1, &mut attachment_link);
+    out
+}
+
+fn write_topics(
+    out: &mut String,
+    topics: &[Topic],
+    level: usize,
+    attachment_link: &mut impl FnMut(&Entry, &Attachment) -> Option<String>,
+) {
+    for topic in topics {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        // Markdown only has 6 heading levels
+        let _ = writeln!(out, "{} {}", "#".repeat(level.min(6)), topic.name);
+        if !topic.desc.trim().is_empty() {
+            let _ = writeln!(out, "\n{}", topic.desc.trim_end());
+        }
+        if !topic.entries.is_empty() {
+            out.push('\n');
+        }
+        for entry in &topic.entries {
+            write_entry(out, entry, 0, attachment_link);
+        }
+        write_topics(out, &topic.children, level + 1, attachment_link);
+    }
+}
+
+/// Write `entry` as a list item, nested `depth` levels deep
+fn write_entry(
+    out: &mut String,
+    entry: &Entry,
+    depth: usize,
+    attachment_link: &mut impl FnMut(&Entry, &Attachment) -> Option<String>,
+) {
+    let indent = "  ".repeat(depth);
+    let marker = match entry.kind {
+        EntryKind::Task if entry.done => "- [x] ",
+        EntryKind::Task => "- [ ] ",
+        EntryKind::Info => "- ",
+    };
+    let _ = writeln!(out, "{indent}{marker}{}", entry.title);
+    if !entry.desc.trim().is_empty() {
+        out.push('\n');
+        for line in entry.desc.trim_end().lines() {
+            if line.is_empty() {
+                out.push('\n');
+            } else {
+                let _ = writeln!(out, "{indent}  {line}");
+            }
+        }
+        out.push('\n');
+    }
+    for attachment in &entry.attachments {
+        let name = attachment.filename.display();
+        match attachment_link(entry, attachment) {
+            Some(link) => {
+                let _ = writeln!(out, "{indent}  - Attachment: [{name}](<{link}>)");
+            }
+            None => {
+                let _ = writeln!(out, "{indent}  - Attachment: {name}");
+            }
+        }
+    }
+    for child in &entry.children {
+        write_entry(out, child, depth + 1, attachment_link);
+    }
+}
+
+/// Build topics and entries from a Markdown document.
+///
+/// Headings become nested topics, and the items of top level bullet lists become entries:
+/// task list items (`- [ ]`, `- [x]`) become tasks, other items info entries. Task list
+/// items in a bullet list nested in an entry become its subtasks. Everything else is kept
+/// as Markdown in the description of the topic or entry it belongs to.
+///
+/// The returned topic is unnamed. It holds what came before the first heading, and the
+/// topics of the top level headings as its children.
+pub fn parse(text: &str) -> Topic {
+    let mut parser = MdParser {
+        text,
+        root: Topic::new_unnamed(),
+        stack: Vec::new(),
+        depth: 0,
+        in_entry_list: false,
+        items: Vec::new(),
+        heading: None,
+    };
+    let options = Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH;
+    for (event, range) in Parser::new_ext(text, options).into_offset_iter() {
+        parser.event(event, range);
+    }
+    while parser.pop_topic() {}
+    parser.root
+}
+
+struct MdParser<'a> {
+    text: &'a str,
+    root: Topic,
+    /// Topics of the headings we're currently under, with their heading level
+    stack: Vec<(usize, Topic)>,
+    /// Nesting depth of block and inline tags
+    depth: usize,
+    /// Whether we're in a top level bullet list
+    in_entry_list: bool,
+    /// The entries of the list items we're in: a top level one, and the subtasks in it
+    items: Vec<ItemState>,
+    /// Level and text of the heading we're in
+    heading: Option<(usize, String)>,
+}
+
+struct ItemState {
+    entry: Entry,
+    /// Nesting depth of the item tag
+    depth: usize,
+    /// Source of the item, kept for the description of its parent if it isn't a subtask
+    range: Range<usize>,
+    /// Still collecting the title (the first paragraph of the item)
+    in_title: bool,
+    desc_parts: Vec<String>,
+    /// In a bullet list directly inside the item, whose items may be subtasks
+    in_sublist: bool,
+    /// The part of `desc_parts` holding the items of the current list that aren't subtasks
+    sublist_part: Option<usize>,
+}
+
+impl MdParser<'_> {
+    fn current_topic(&mut self) -> &mut Topic {
+        match self.stack.last_mut() {
+            Some((_, topic)) => topic,
+            None => &mut self.root,
+        }
+    }
+    /// Attach the innermost topic to its parent. Returns false if there was none.
+    fn pop_topic(&mut self) -> bool {
+        let Some((_, topic)) = self.stack.pop() else {
+            return false;
+        };
+        self.current_topic().children.push(topic);
+        true
+    }
+    fn event(&mut self, event: Event, range: Range<usize>) {
+        match event {
+            Event::Start(tag) => {
+                self.start(&tag, range);
+                self.depth += 1;
+            }
+            Event::End(tag) => {
+                self.depth -= 1;
+                self.end(tag);
+            }
+            Event::TaskListMarker(done) => {
+                if let Some(item) = self.items.last_mut()
+                    && item.in_title
+                {
+                    item.entry.kind = EntryKind::Task;
+                    item.entry.done = done;
+                }
+            }
+            Event::Text(text) | Event::Code(text) => self.push_inline_text(&text),
+            Event::SoftBreak | Event::HardBreak => self.push_inline_text(" "),
+            _ => {}
+        }
+    }
+    fn push_inline_text(&mut self, text: &str) {
+        if let Some((_, heading)) = &mut self.heading {
+            heading.push_str(text);
+        } else if let Some(item) = self.items.last_mut()
+            && item.in_title
+        {
+            item.entry.title.push_str(text);
+        }
+    }
+    fn push_item(&mut self, range: Range<usize>) {
+        self.items.push(ItemState {
+            entry: Entry {
+                kind: EntryKind::Info,
+                id: Id::new(),
+                ..Entry::default()
+            },
+            depth: self.depth,
+            range,
+            in_title: true,
+            desc_parts: Vec::new(),
+            in_sublist: false,
+            sublist_part: None,
+        });
+    }
+    fn start(&mut self, tag: &Tag, range: Range<usize>) {
+        let source = &self.text[range.clone()];
+        let item_depth = self.items.last().map(|item| item.depth);
+        match (self.depth, tag) {
+            (0, Tag::Heading { level, .. }) => {
+                self.heading = Some((*level as usize, String::new()));
+            }
+            (0, Tag::List(None)) => self.in_entry_list = true,
+            (0, _) => {
+                let desc = &mut self.current_topic().desc;
+                if !desc.is_empty() {
+                    desc.push_str("\n\n");
+                }
+                desc.push_str(source.trim_end());
+            }
+            (1, Tag::Item) if self.in_entry_list => self.push_item(range),
+            (depth, Tag::Item)
+                if self
+                    .items
+                    .last()
+                    .is_some_and(|item| item.in_sublist && item.depth + 2 == depth) =>
+            {
+                self.push_item(range);
+            }
+            (depth, Tag::List(None)) if item_depth == Some(depth - 1) => {
+                let Some(item) = self.items.last_mut() else {
+                    return;
+                };
+                item.in_title = false;
+                item.in_sublist = true;
+                item.sublist_part = None;
+            }
+            (depth, tag) if item_depth == Some(depth - 1) && is_block(tag) => {
+                let Some(item) = self.items.last_mut() else {
+                    return;
+                };
+                let is_title =
+                    item.in_title && matches!(tag, Tag::Paragraph) && item.entry.title.is_empty();
+                if !is_title {
+                    item.in_title = false;
+                    item.desc_parts.push(dedent(source));
+                }
+            }
+            _ => {}
+        }
+    }
+    fn end(&mut self, tag: TagEnd) {
+        match (self.depth, tag) {
+            (0, TagEnd::Heading(_)) => {
+                let Some((level, name)) = self.heading.take() else {
+                    return;
+                };
+                while self.stack.last().is_some_and(|(l, _)| *l >= level) {
+                    self.pop_topic();
+                }
+                self.stack.push((level, Topic::new(name.trim().to_owned())));
+            }
+            (0, TagEnd::List(_)) => self.in_entry_list = false,
+            (depth, TagEnd::Item) if self.items.last().is_some_and(|item| item.depth == depth) => {
+                self.end_item();
+            }
+            (depth, TagEnd::List(_)) => {
+                if let Some(item) = self.items.last_mut()
+                    && item.depth + 1 == depth
+                {
+                    item.in_sublist = false;
+                }
+            }
+            (depth, TagEnd::Paragraph) => {
+                if let Some(item) = self.items.last_mut()
+                    && item.depth + 1 == depth
+                {
+                    item.in_title = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Add the entry of the item that just ended to its topic, or to its parent entry
+    fn end_item(&mut self) {
+        let Some(ItemState {
+            mut entry,
+            range,
+            desc_parts,
+            ..
+        }) = self.items.pop()
+        else {
+            return;
+        };
+        entry.title = String::from(entry.title.trim());
+        entry.desc = desc_parts.join("\n\n");
+        let Some(parent) = self.items.last_mut() else {
+            self.current_topic().entries.push(entry);
+            return;
+        };
+        if entry.kind == EntryKind::Task {
+            parent.entry.children.push(entry);
+            return;
+        }
+        // Not a subtask, so it stays in the description, along with the other such items
+        let source = dedent(&self.text[range]);
+        if let Some(idx) = parent.sublist_part {
+            let part = &mut parent.desc_parts[idx];
+            part.push('\n');
+            part.push_str(&source);
+        } else {
+            parent.sublist_part = Some(parent.desc_parts.len());
+            parent.desc_parts.push(source);
+        }
+    }
+}
+
+const fn is_block(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Paragraph
+            | Tag::Heading { .. }
+            | Tag::BlockQuote(_)
+            | Tag::CodeBlock(_)
+            | Tag::HtmlBlock
+            | Tag::List(_)
+            | Tag::Table(_)
+            | Tag::FootnoteDefinition(_)
+    )
+}
+
+/// Remove the indentation of a block nested in a list item.
+///
+/// The first line starts at the block, so only the following lines are indented.
+fn dedent(source: &str) -> String {
+    let source = source.trim_end();
+    let mut lines = source.lines();
+    let first = lines.next().unwrap_or_default();
+    let rest: Vec<&str> = lines.collect();
+    let indent = rest
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut out = first.to_owned();
+    for line in rest {
+        out.push('\n');
+        out.push_str(line.get(indent..).unwrap_or_default());
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{export, parse, to_markdown},
+        crate::data::{Attachment, Entry, EntryKind, Topic},
+    };
+
+    #[test]
+    fn test_markdown_export_attachment_paths() {
+        let dir = std::env::temp_dir().join(format!("setodo-md-export-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let mut topic = Topic::new("Work".into());
+        let mut entry = Entry::new_task("Task".into());
+        entry.attachments.push(Attachment {
+            filename: "../../escaped.txt".into(),
+            data: b"data"[..].into(),
+        });
+        let id = entry.id;
+        topic.entries.push(entry);
+        export(&[topic], &dir.join("out.md"), true).unwrap();
+        let written = dir
+            .join("out_files")
+            .join(id.to_string())
+            .join("escaped.txt");
+        assert!(written.is_file());
+        assert!(!dir.join("escaped.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_markdown_export() {
+        let mut topic = Topic::new("Work".into());
+        topic.desc = "Things to do".into();
+        let mut task = Entry::new_task("Write report".into());
+        task.desc = "Quarterly\n\nDue soon".into();
+        topic.entries.push(task);
+        let mut done = Entry::new_task("Send mail".into());
+        done.done = true;
+        done.children
+            .push(Entry::new_task("Check the address".into()));
+        topic.entries.push(done);
+        topic.entries.push(Entry {
+            title: "Office is closed on Fridays".into(),
+            kind: EntryKind::Info,
+            ..Entry::default()
+        });
+        topic.children.push(Topic::new("Meetings".into()));
+        let md = to_markdown(&[topic], |_, _| None);
+        assert_eq!(
+            md,
+            "# Work\n\
+             \n\
+             Things to do\n\
+             \n\
+             - [ ] Write report\n\
+             \n  Quarterly\n\
+             \n  Due soon\n\
+             \n\
+             - [x] Send mail\n\
+             \x20 - [ ] Check the address\n\
+             - Office is closed on Fridays\n\
+             \n\
+             ## Meetings\n"
+        );
+    }
+    #[test]
+    fn test_markdown_import() {
+        let root = parse(
+            "Intro text\n\
+             \n\
+             - [ ] loose task\n\
+             \n\
+             # Work\n\
+             \n\
+             Things to do\n\
+             \n\
+             - [ ] Write *the* report\n\
+             \n  Quarterly\n\
+             \n  - detail\n\
+             \n\
+             - [x] Send mail\n\
+             - Office is closed on Fridays\n\
+             \n\
+             ### Meetings\n\
+             \n\
+             1. not an entry\n\
+             \n\
+             ## Home\n",
+        );
+        assert_eq!(root.desc, "Intro text");
+        assert_eq!(root.entries[0].title, "loose task");
+        let work = &root.children[0];
+        assert_eq!(work.name, "Work");
+        assert_eq!(work.desc, "Things to do");
+        let titles: Vec<_> = work.entries.iter().map(|en| en.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            [
+                "Write the report",
+                "Send mail",
+                "Office is closed on Fridays"
+            ]
+        );
+        assert_eq!(work.entries[0].kind, EntryKind::Task);
+        assert!(!work.entries[0].done);
+        assert_eq!(work.entries[0].desc, "Quarterly\n\n- detail");
+        assert!(work.entries[1].done);
+        assert_eq!(work.entries[2].kind, EntryKind::Info);
+        // Skipped heading levels still nest
+        let children: Vec<_> = work.children.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(children, ["Meetings", "Home"]);
+        assert_eq!(work.children[0].desc, "1. not an entry");
+    }
+
+    #[test]
+    fn test_markdown_subtask_round_trip() {
+        let mut topic = Topic::new("Work".into());
+        let mut task = Entry::new_task("Release".into());
+        task.desc = "Notes".into();
+        task.attachments.push(Attachment {
+            filename: "plan.txt".into(),
+            data: b"plan"[..].into(),
+        });
+        let mut sub = Entry::new_task("Write changelog".into());
+        sub.desc = "From the commits".into();
+        sub.done = true;
+        sub.children.push(Entry::new_task("Ask for review".into()));
+        task.children.push(sub);
+        task.children.push(Entry::new_task("Tag".into()));
+        topic.entries.push(task);
+        let root = parse(&to_markdown(&[topic], |_, _| None));
+        let task = &root.children[0].entries[0];
+        assert_eq!(task.title, "Release");
+        assert_eq!(task.desc, "Notes\n\n- Attachment: plan.txt");
+        let titles: Vec<_> = task.children.iter().map(|en| en.title.as_str()).collect();
+        assert_eq!(titles, ["Write changelog", "Tag"]);
+        let sub = &task.children[0];
+        assert!(sub.done);
+        assert_eq!(sub.desc, "From the commits");
+        assert_eq!(sub.children[0].title, "Ask for review");
+        assert_eq!(sub.children[0].kind, EntryKind::Task);
+    }
+}
@@ -0,0 +1,426 @@
+use {
+    super::{ImportStats, selected_or_new_topic},
+    crate::{
+        app::TodoAppPersistent,
+        data::{Entry, EntryKind, Id, Recurrence, Topic, find_entry_mut},
+    },
+    chrono::{Datelike as _, NaiveDate},
+    std::fmt::Write as _,
+};
+
+const DATE_FMT: &str = "%Y-%m-%d";
+
+/// Write the tasks in `topics` as todo.txt lines.
+///
+/// The topic path becomes a `+project` (with spaces replaced by `_`), and due date,
+/// scheduled date and recurrence become `due:`, `t:` and `rec:` tags.
+/// The entry id is written as an `id:` tag, so importing the file again updates the entries
+/// instead of duplicating them. Subtasks follow their parent task, linked to it by a
+/// `parent:` tag with its id.
+/// Titles are escaped where needed, so that they read back unchanged (see [`escape_title`]).
+///
+/// Info entries and descriptions have no equivalent in todo.txt, and are left out.
+pub fn export(topics: &[Topic]) -> String {
+    let mut out = String::new();
+    write_topics(&mut out, topics, "");
+    out
+}
+
+fn write_topics(out: &mut String, topics: &[Topic], parent: &str) {
+    for topic in topics {
+        let project = if parent.is_empty() {
+            project_name(&topic.name)
+        } else {
+            format!("{parent}/{}", project_name(&topic.name))
+        };
+        write_entries(out, &topic.entries, &project, None);
+        write_topics(out, &topic.children, &project);
+    }
+}
+
+fn project_name(topic_name: &str) -> String {
+    topic_name.split_whitespace().collect::<Vec<_>>().join("_")
+}
+
+fn write_entries(out: &mut String, entries: &[Entry], project: &str, parent: Option<Id>) {
+    for entry in entries {
+        if entry.kind == EntryKind::Task {
+            write_entry(out, entry, project, parent);
+            write_entries(out, &entry.children, project, Some(entry.id));
+        }
+    }
+}
+
+fn write_entry(out: &mut String, entry: &Entry, project: &str, parent: Option<Id>) {
+    if entry.done {
+        out.push_str("x ");
+    }
+    out.push_str(&escape_title(&entry.title));
+    if !project.is_empty() {
+        let _ = write!(out, " +{project}");
+    }
+    if let Some(due) = entry.due {
+        let _ = write!(out, " due:{}", due.format(DATE_FMT));
+    }
+    if let Some(scheduled) = entry.scheduled {
+        let _ = write!(out, " t:{}", scheduled.format(DATE_FMT));
+    }
+    if let Some(recurrence) = entry.recurrence {
+        let rec = match recurrence {
+            Recurrence::Days(n) => format!("{n}d"),
+            Recurrence::Weekdays => "1b".into(),
+            Recurrence::MonthDay(_) => "1m".into(),
+        };
+        let _ = write!(out, " rec:{rec}");
+    }
+    if let Some(parent) = parent {
+        let _ = write!(out, " parent:{parent}");
+    }
+    let _ = writeln!(out, " id:{}", entry.id);
+}
+
+/// Keys of the tags we understand
+const TAG_KEYS: [&str; 5] = ["due", "t", "rec", "id", "parent"];
+
+/// Escape `title` so that it reads back as exactly the title, and nothing else.
+///
+/// Backslashes escape themselves, line breaks and tabs (`\n`, `\r`, `\t`), and the spaces
+/// that splitting into words would lose (`\s`). Words that would be taken for a project or
+/// a tag get a backslash before the `+` or `:`, and so does a first word that would be taken
+/// for the done mark or a date.
+fn escape_title(title: &str) -> String {
+    let mut escaped = String::new();
+    let last = title.len().saturating_sub(1);
+    for (i, c) in title.char_indices() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ' ' if i == 0 || i == last || escaped.ends_with(' ') => escaped.push_str("\\s"),
+            c => escaped.push(c),
+        }
+    }
+    let words: Vec<String> = escaped
+        .split(' ')
+        .enumerate()
+        .map(|(i, word)| {
+            let is_tag = word
+                .split_once(':')
+                .is_some_and(|(key, _)| TAG_KEYS.contains(&key));
+            if is_tag {
+                word.replacen(':', "\\:", 1)
+            } else if word.starts_with('+')
+                || (i == 0 && (word == "x" || NaiveDate::parse_from_str(word, DATE_FMT).is_ok()))
+            {
+                format!("\\{word}")
+            } else {
+                word.to_owned()
+            }
+        })
+        .collect();
+    words.join(" ")
+}
+
+/// Undo [`escape_title`] for one word
+fn unescape_word(word: &str) -> String {
+    let mut out = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('s') => ' ',
+            Some(c) => c,
+            None => '\\',
+        });
+    }
+    out
+}
+
+/// A parsed todo.txt line
+struct Line {
+    done: bool,
+    /// Everything that isn't a project or one of the tags we understand.
+    ///
+    /// This includes the priority, so it survives a round trip.
+    title: String,
+    project: Option<String>,
+    due: Option<NaiveDate>,
+    scheduled: Option<NaiveDate>,
+    recurrence: Option<Recurrence>,
+    id: Option<Id>,
+    /// Id of the task this is a subtask of
+    parent: Option<Id>,
+}
+
+fn parse_line(line: &str) -> Option<Line> {
+    // Only spaces and tabs separate words, so other whitespace in titles is kept
+    let mut words = line
+        .split([' ', '\t'])
+        .filter(|word| !word.is_empty())
+        .peekable();
+    words.peek()?;
+    let done = words.next_if_eq(&"x").is_some();
+    // Completion and creation dates. We don't keep track of these.
+    while words
+        .next_if(|word| NaiveDate::parse_from_str(word, DATE_FMT).is_ok())
+        .is_some()
+    {}
+    let mut parsed = Line {
+        done,
+        title: String::new(),
+        project: None,
+        due: None,
+        scheduled: None,
+        recurrence: None,
+        id: None,
+        parent: None,
+    };
+    let mut title_words = Vec::new();
+    for word in words {
+        let date = |value: &str| NaiveDate::parse_from_str(value, DATE_FMT).ok();
+        let understood = match word.split_once(':') {
+            Some(("due", value)) => date(value).map(|d| parsed.due = Some(d)),
+            Some(("t", value)) => date(value).map(|d| parsed.scheduled = Some(d)),
+            Some(("rec", value)) => parse_recurrence(value).map(|r| parsed.recurrence = Some(r)),
+            Some(("id", value)) => value.parse().ok().map(|id| parsed.id = Some(id)),
+            Some(("parent", value)) => value.parse().ok().map(|id| parsed.parent = Some(id)),
+            _ => match word.strip_prefix('+') {
+                Some(project) if !project.is_empty() && parsed.project.is_none() => {
+                    parsed.project = Some(project.to_owned());
+                    Some(())
+                }
+                _ => None,
+            },
+        };
+        if understood.is_none() {
+            title_words.push(word);
+        }
+    }
+    parsed.title = title_words
+        .into_iter()
+        .map(unescape_word)
+        .collect::<Vec<_>>()
+        .join(" ");
+    // Monthly recurrence happens on the day of the month it's due
+    if let Some(Recurrence::MonthDay(day)) = &mut parsed.recurrence
+        && let Some(due) = parsed.due
+    {
+        *day = due.day();
+    }
+    Some(parsed)
+}
+
+/// Parse the value of a `rec:` tag, like `2d`, `1w`, `1b` (business days), or `+1m`
+fn parse_recurrence(value: &str) -> Option<Recurrence> {
+    let value = value.strip_prefix('+').unwrap_or(value);
+    let unit_pos = value.find(|c: char| !c.is_ascii_digit())?;
+    let (n, unit) = value.split_at(unit_pos);
+    let n: u32 = if n.is_empty() { 1 } else { n.parse().ok()? };
+    match unit {
+        "d" => Some(Recurrence::Days(n)),
+        "w" => n.checked_mul(7).map(Recurrence::Days),
+        "b" if n == 1 => Some(Recurrence::Weekdays),
+        "m" if n == 1 => Some(Recurrence::MonthDay(1)),
+        _ => None,
+    }
+}
+
+/// Import todo.txt lines into `per`.
+///
+/// Lines with the id of an existing entry update that entry. Other lines are added as new
+/// tasks to the topic named by their `+project`, which is created if needed. Lines without a
+/// project go to the selected topic, or a topic called `fallback_name`. Lines with the
+/// `parent:` id of an existing task are added as its subtasks instead.
+pub fn import(text: &str, per: &mut TodoAppPersistent, fallback_name: &str) -> ImportStats {
+    let mut stats = ImportStats::default();
+    let today = chrono::Local::now().date_naive();
+    for line in text.lines().filter_map(parse_line) {
+        if let Some(id) = line.id
+            && let Some(entry) = find_entry_mut(&mut per.topics, id)
+        {
+            let completes_recurring = line.done && line.recurrence.is_some();
+            if completes_recurring && line.due != entry.due {
+                // This occurrence was already rolled forward by an earlier import
+                continue;
+            }
+            let before = entry.clone();
+            entry.title = line.title;
+            entry.due = line.due;
+            entry.scheduled = line.scheduled;
+            entry.recurrence = line.recurrence;
+            entry.done = line.done;
+            if completes_recurring {
+                entry.roll_forward(today);
+            }
+            if *entry != before {
+                stats.entries_updated += 1;
+            }
+            continue;
+        }
+        let mut entry = Entry::new_task(line.title);
+        // Keep the id, so a second import of the same file doesn't duplicate the entry
+        if let Some(id) = line.id {
+            entry.id = id;
+        }
+        entry.done = line.done;
+        entry.due = line.due;
+        entry.scheduled = line.scheduled;
+        entry.recurrence = line.recurrence;
+        if let Some(parent) = line
+            .parent
+            .and_then(|id| find_entry_mut(&mut per.topics, id))
+        {
+            parent.children.push(entry);
+            stats.entries_added += 1;
+            continue;
+        }
+        let topic = match &line.project {
+            Some(project) => project_topic(&mut per.topics, project, &mut stats),
+            None => selected_or_new_topic(per, fallback_name),
+        };
+        topic.entries.push(entry);
+        stats.entries_added += 1;
+    }
+    stats
+}
+
+/// The topic for a `/` separated project path, creating missing topics
+fn project_topic<'t>(
+    mut topics: &'t mut Vec<Topic>,
+    project: &str,
+    stats: &mut ImportStats,
+) -> &'t mut Topic {
+    let mut segments = project.split('/').filter(|seg| !seg.is_empty()).peekable();
+    loop {
+        let segment = segments.next().unwrap_or(project);
+        let idx = if let Some(idx) = topics
+            .iter()
+            .position(|topic| project_name(&topic.name) == segment)
+        {
+            idx
+        } else {
+            topics.push(Topic::new(segment.replace('_', " ")));
+            stats.topics_added += 1;
+            topics.len() - 1
+        };
+        if segments.peek().is_none() {
+            return &mut topics[idx];
+        }
+        topics = &mut topics[idx].children;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{export, import, parse_line},
+        crate::{
+            app::TodoAppPersistent,
+            data::{Entry, Recurrence, Topic},
+        },
+        chrono::NaiveDate,
+    };
+
+    #[test]
+    fn test_todotxt_round_trip() {
+        let mut per = TodoAppPersistent::default();
+        let mut work = Topic::new("Day job".into());
+        let mut child = Topic::new("Reports".into());
+        let mut task = Entry::new_task("(A) Write report @office".into());
+        task.due = NaiveDate::from_ymd_opt(2024, 3, 20);
+        task.recurrence = Some(Recurrence::Days(7));
+        child.entries.push(task);
+        work.children.push(child);
+        per.topics.push(work);
+        let text = export(&per.topics);
+        let id = per.topics[0].children[0].entries[0].id;
+        assert_eq!(
+            text,
+            format!("(A) Write report @office +Day_job/Reports due:2024-03-20 rec:7d id:{id}\n")
+        );
+        // Unchanged, so nothing happens
+        let stats = import(&text, &mut per, "todo");
+        assert_eq!(
+            (
+                stats.entries_added,
+                stats.entries_updated,
+                stats.topics_added
+            ),
+            (0, 0, 0)
+        );
+        // Edited elsewhere, and a new task added
+        let text = format!(
+            "{}\n2024-03-01 Call Bob +Day_job/Meetings\n",
+            text.trim_end().replace("Write report", "Write the report")
+        );
+        let stats = import(&text, &mut per, "todo");
+        assert_eq!(
+            (
+                stats.entries_added,
+                stats.entries_updated,
+                stats.topics_added
+            ),
+            (1, 1, 1)
+        );
+        let work = &per.topics[0];
+        assert_eq!(
+            work.children[0].entries[0].title,
+            "(A) Write the report @office"
+        );
+        assert_eq!(work.children[1].name, "Meetings");
+        assert_eq!(work.children[1].entries[0].title, "Call Bob");
+        // Too many weeks to count in days, so it's not a recurrence
+        let line = parse_line("Someday rec:1000000000w").unwrap();
+        assert_eq!(line.recurrence, None);
+        assert_eq!(line.title, "Someday rec:1000000000w");
+    }
+
+    #[test]
+    fn test_todotxt_title_escaping() {
+        let mut per = TodoAppPersistent::default();
+        let mut topic = Topic::new("Work".into());
+        for title in [
+            "Fix +urgent bug due:2024-03-20",
+            "2024-03-01 x t:now id:1 rec:1d",
+            "x",
+            " Two  spaces\nand a line break, C:\\temp\\ ",
+        ] {
+            topic.entries.push(Entry::new_task(title.into()));
+        }
+        per.topics.push(topic);
+        let text = export(&per.topics);
+        for (line, entry) in text.lines().zip(&per.topics[0].entries) {
+            let parsed = parse_line(line).unwrap();
+            assert_eq!(parsed.title, entry.title);
+            assert_eq!(parsed.project.as_deref(), Some("Work"));
+            assert_eq!((parsed.done, parsed.due), (false, None));
+        }
+        let stats = import(&text, &mut per, "todo");
+        assert_eq!((stats.entries_added, stats.entries_updated), (0, 0));
+    }
+
+    #[test]
+    fn test_todotxt_subtasks() {
+        let mut topic = Topic::new("Work".into());
+        let mut task = Entry::new_task("Release".into());
+        let mut sub = Entry::new_task("Write notes".into());
+        sub.children.push(Entry::new_task("Ask for review".into()));
+        task.children.push(sub);
+        topic.entries.push(task);
+        let text = export(&[topic]);
+        assert_eq!(text.lines().count(), 3);
+        let mut per = TodoAppPersistent::default();
+        let stats = import(&text, &mut per, "todo");
+        assert_eq!(stats.entries_added, 3);
+        let entries = &per.topics[0].entries;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].children[0].children[0].title, "Ask for review");
+    }
+}
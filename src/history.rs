@@ -0,0 +1,148 @@
+use crate::{
+    app::TodoAppPersistent,
+    data::{Topic, same_content_as_clone},
+};
+
+/// Maximum number of undo steps kept around
+const MAX_DEPTH: usize = 100;
+/// Changes made within this many seconds of the first one are undone together
+const COALESCE_SECS: f64 = 1.0;
+
+struct Snapshot {
+    topics: Vec<Topic>,
+    topic_sel: Vec<usize>,
+}
+
+impl Snapshot {
+    fn of(per: &TodoAppPersistent) -> Self {
+        Self {
+            topics: per.topics.clone(),
+            topic_sel: per.topic_sel.clone(),
+        }
+    }
+    fn restore(&self, per: &mut TodoAppPersistent) {
+        per.topics.clone_from(&self.topics);
+        per.topic_sel.clone_from(&self.topic_sel);
+    }
+}
+
+/// Undo/redo history of the topic tree.
+///
+/// Rather than requiring every edit to describe itself, the tree is compared against the
+/// last known state, and a snapshot is recorded whenever it differs.
+///
+/// A change opens a coalescing window, during which the tree isn't looked at at all.
+/// It's compared (and snapshotted, if changed further) again only once the window is over,
+/// so continuous edits like typing don't copy the whole tree every frame.
+pub struct History {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// The last known state
+    current: Snapshot,
+    /// End of the coalescing window, if one is open
+    window_end: Option<f64>,
+}
+
+impl History {
+    pub fn new(per: &TodoAppPersistent) -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            current: Snapshot::of(per),
+            window_end: None,
+        }
+    }
+    /// Forget all history, and start over from `per`
+    pub fn reset(&mut self, per: &TodoAppPersistent) {
+        *self = Self::new(per);
+    }
+    /// Record the state of `per` if it changed. Returns whether it changed.
+    pub fn track(&mut self, per: &TodoAppPersistent, now: f64) -> bool {
+        match self.window_end {
+            Some(end) if now < end => false,
+            Some(_) => self.close_window(per),
+            None => {
+                if same_content_as_clone(&per.topics, &self.current.topics) {
+                    // Keep the selection up to date, so undo restores a sensible one
+                    self.current.topic_sel.clone_from(&per.topic_sel);
+                    return false;
+                }
+                let prev = std::mem::replace(&mut self.current, Snapshot::of(per));
+                self.undo.push(prev);
+                if self.undo.len() > MAX_DEPTH {
+                    self.undo.remove(0);
+                }
+                self.redo.clear();
+                self.window_end = Some(now + COALESCE_SECS);
+                true
+            }
+        }
+    }
+    /// Take in the changes made since the coalescing window opened, without recording a
+    /// new undo step for them. Returns whether there were any.
+    fn close_window(&mut self, per: &TodoAppPersistent) -> bool {
+        self.window_end = None;
+        if same_content_as_clone(&per.topics, &self.current.topics) {
+            return false;
+        }
+        self.current = Snapshot::of(per);
+        true
+    }
+    /// Undo the last change. Returns whether there was anything to undo.
+    pub fn undo(&mut self, per: &mut TodoAppPersistent, now: f64) -> bool {
+        self.track(per, now);
+        self.close_window(per);
+        let Some(snap) = self.undo.pop() else {
+            return false;
+        };
+        snap.restore(per);
+        self.redo.push(std::mem::replace(&mut self.current, snap));
+        true
+    }
+    /// Redo the last undone change. Returns whether there was anything to redo.
+    pub fn redo(&mut self, per: &mut TodoAppPersistent, now: f64) -> bool {
+        self.track(per, now);
+        self.close_window(per);
+        let Some(snap) = self.redo.pop() else {
+            return false;
+        };
+        snap.restore(per);
+        self.undo.push(std::mem::replace(&mut self.current, snap));
+        true
+    }
+    pub const fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+    pub const fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::History,
+        crate::{app::TodoAppPersistent, data::Topic},
+    };
+
+    #[test]
+    fn test_coalesce() {
+        let mut per = TodoAppPersistent::default();
+        let mut history = History::new(&per);
+        per.topics.push(Topic::new("A".into()));
+        assert!(history.track(&per, 0.0));
+        // Within the window, so part of the same step
+        per.topics[0].name.push('b');
+        assert!(!history.track(&per, 0.5));
+        assert!(history.track(&per, 1.5));
+        per.topics[0].name.push('c');
+        assert!(history.track(&per, 2.0));
+        history.undo(&mut per, 2.1);
+        assert_eq!(per.topics[0].name, "Ab");
+        history.undo(&mut per, 2.2);
+        assert!(per.topics.is_empty());
+        assert!(!history.can_undo());
+        history.redo(&mut per, 2.3);
+        assert_eq!(per.topics[0].name, "Ab");
+    }
+}
@@ -0,0 +1,217 @@
+use {
+    crate::{cli::Command, data::fnv1a},
+    eframe::egui,
+    existing_instance::{Endpoint, Listener, Msg, Stream},
+    serde::{Deserialize, Serialize},
+    std::{
+        error::Error,
+        path::{Path, PathBuf},
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+            mpsc::{Receiver, Sender},
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// The endpoint name of the instance that has `data_file_path` open.
+///
+/// Each data file gets its own instance, so the name is derived from its canonical path.
+pub fn endpoint_name(data_file_path: &Path) -> String {
+    let hash = fnv1a(
+        canonical_path(data_file_path)
+            .as_os_str()
+            .as_encoded_bytes(),
+    );
+    format!("rust-setodo-{hash:016x}")
+}
+
+/// Canonical form of `path`, which also works if the file doesn't exist yet
+pub fn canonical_path(path: &Path) -> PathBuf {
+    if let Ok(path) = std::fs::canonicalize(path) {
+        return path;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            std::fs::canonicalize(parent).map_or_else(|_| path.into(), |parent| parent.join(name))
+        }
+        _ => path.into(),
+    }
+}
+
+/// A message sent by a second launch of setodo to the running instance
+#[derive(Serialize, Deserialize)]
+pub enum Request {
+    /// Bring the window back
+    Show,
+    /// Run a command on the data file
+    Command {
+        /// The data file the sender wants the command to apply to
+        data_file_path: PathBuf,
+        cmd: Command,
+    },
+}
+
+/// Output of a forwarded command, or the error message
+pub type Reply = Result<String, String>;
+
+/// A command received from another process, waiting to be applied by the app
+pub struct IncomingCommand {
+    pub data_file_path: PathBuf,
+    pub cmd: Command,
+    stream: Stream,
+    received: Instant,
+}
+
+impl IncomingCommand {
+    /// Whether the sender has given up waiting for the reply by now.
+    ///
+    /// Such commands must not run, or retrying them would run them twice.
+    pub fn expired(&self) -> bool {
+        self.received.elapsed() > COMMAND_DEADLINE
+    }
+    pub fn reply(mut self, reply: &Reply) {
+        send_msg(&mut self.stream, reply);
+    }
+}
+
+fn send_msg(stream: &mut Stream, msg: &impl Serialize) {
+    match rmp_serde::to_vec(msg) {
+        Ok(bytes) => stream.send(Msg::Bytes(bytes)),
+        Err(e) => eprintln!("IPC: failed to encode message: {e}"),
+    }
+}
+
+/// Take over the endpoint of `new_path`, for switching to it from `current_path`.
+///
+/// Returns `None` if they're the same file, and fails if another instance has it open,
+/// so that no two instances ever write the same file.
+pub fn claim_endpoint(
+    current_path: &Path,
+    new_path: &Path,
+) -> Result<Option<Listener>, Box<dyn Error>> {
+    if canonical_path(current_path) == canonical_path(new_path) {
+        return Ok(None);
+    }
+    match existing_instance::establish_endpoint(&endpoint_name(new_path), true)? {
+        Endpoint::New(listener) => Ok(Some(listener)),
+        Endpoint::Existing(_) => Err(format!(
+            "'{}' is already open in another setodo window",
+            new_path.display()
+        )
+        .into()),
+    }
+}
+
+/// Commands received from other processes, waiting to be applied by the app.
+///
+/// The endpoint they come in through is listened on until this is dropped.
+pub struct IncomingCommands {
+    rx: Receiver<IncomingCommand>,
+    stop: Arc<AtomicBool>,
+}
+
+impl IncomingCommands {
+    pub fn try_iter(&self) -> impl Iterator<Item = IncomingCommand> + '_ {
+        self.rx.try_iter()
+    }
+}
+
+impl Drop for IncomingCommands {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Listen for other instances in the background.
+///
+/// `Show` requests are handled right away, commands are passed on to the app through the
+/// returned [`IncomingCommands`]. Dropping it closes the endpoint.
+pub fn spawn_listener(listener: Listener, egui_ctx: egui::Context) -> IncomingCommands {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = Arc::clone(&stop);
+    std::thread::spawn(move || {
+        while !thread_stop.load(Ordering::Relaxed) {
+            if let Some(stream) = listener.accept() {
+                handle_connection(stream, &egui_ctx, &tx);
+            }
+            std::thread::sleep(Duration::from_millis(250));
+        }
+    });
+    IncomingCommands { rx, stop }
+}
+
+fn handle_connection(mut stream: Stream, egui_ctx: &egui::Context, tx: &Sender<IncomingCommand>) {
+    // Older versions just connect without saying anything, which means "show"
+    let request = match stream.recv() {
+        Some(Msg::Bytes(bytes)) => match rmp_serde::from_slice(&bytes) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("IPC: failed to decode request: {e}");
+                send_msg(&mut stream, &Reply::Err(format!("Invalid request: {e}")));
+                return;
+            }
+        },
+        _ => Request::Show,
+    };
+    match request {
+        Request::Show => {
+            egui_ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+            egui_ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            send_msg(&mut stream, &Reply::Ok(String::new()));
+        }
+        Request::Command {
+            data_file_path,
+            cmd,
+        } => {
+            if tx
+                .send(IncomingCommand {
+                    data_file_path,
+                    cmd,
+                    stream,
+                    received: Instant::now(),
+                })
+                .is_ok()
+            {
+                egui_ctx.request_repaint();
+            }
+        }
+    }
+}
+
+/// How long the running instance may take to get to a command before it's dropped
+const COMMAND_DEADLINE: Duration = Duration::from_secs(5);
+/// How long to wait for the running instance to reply to a request.
+///
+/// Longer than [`COMMAND_DEADLINE`], so a command that times out was never run.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(6);
+
+/// Send `request` to the running instance, and wait for its reply
+pub fn send_request(mut stream: Stream, request: &Request) -> Result<String, Box<dyn Error>> {
+    stream.send(Msg::Bytes(rmp_serde::to_vec(request)?));
+    // Commands are answered from the app's update loop, which may not run while its window
+    // is hidden, so don't wait forever
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(stream.recv());
+    });
+    match rx.recv_timeout(REPLY_TIMEOUT) {
+        Ok(Some(Msg::Bytes(bytes))) => {
+            let reply: Reply = rmp_serde::from_slice(&bytes)?;
+            Ok(reply?)
+        }
+        Ok(_) => Err("The running instance didn't reply".into()),
+        Err(_) => Err(
+            "The running instance didn't reply in time, so the command wasn't run. \
+                       If its window is hidden, run `setodo` to show it, and try again."
+                .into(),
+        ),
+    }
+}
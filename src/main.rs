@@ -12,35 +12,35 @@
 
 use {
     app::{TodoApp, default_data_file_path},
+    cli::{COMMANDS_HELP, Command},
     eframe::egui::{self, ViewportBuilder, Visuals},
-    existing_instance::Endpoint,
-    std::{path::PathBuf, time::Duration},
+    existing_instance::{Endpoint, Stream},
+    recovery::RecoveryApp,
+    std::path::{Path, PathBuf},
 };
 
 mod app;
+mod cli;
 mod cmd;
 mod data;
+mod dir_store;
+mod formats;
+mod history;
+mod ipc;
+mod merge;
+mod recovery;
 mod tree;
 mod ui;
 
 fn main() {
-    let ipc_listener = match existing_instance::establish_endpoint("rust-setodo", true).unwrap() {
-        Endpoint::New(listener) => listener,
-        Endpoint::Existing(_) => {
-            return;
-        }
-    };
-    let native_options = eframe::NativeOptions {
-        viewport: ViewportBuilder::default().with_inner_size(egui::vec2(620., 480.)),
-        ..Default::default()
-    };
     argwerk::define! {
-        #[usage = "setodo [options...]"]
+        #[usage = "setodo [options...] [<command> [args...]]"]
         struct Args {
             help: bool,
             datafile_path: PathBuf = default_data_file_path(),
+            command: Option<(String, Vec<String>)>,
         }
-        /// Use a custom data file instead of default (~/.setodo.dat)
+        /// Use a custom data file (or directory store) instead of default (~/.setodo.dat)
         ["-f" | "--file", #[os] path] => {
             datafile_path = path.into();
         }
@@ -49,6 +49,10 @@ fn main() {
             println!("{HELP}");
             help = true;
         }
+        /// Run <command> on the data file, in the running instance if there is one.
+        [name, #[rest] cmd_args] => {
+            command = Some((name, cmd_args));
+        }
     }
     let args = match Args::args() {
         Ok(args) => args,
@@ -58,65 +62,107 @@ fn main() {
         }
     };
     if args.help {
+        println!("{COMMANDS_HELP}");
         return;
     }
+    let command = match args
+        .command
+        .as_ref()
+        .map(|(name, cmd_args)| Command::parse(name, cmd_args))
+    {
+        Some(Ok(cmd)) => Some(cmd),
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let ipc_listener =
+        match existing_instance::establish_endpoint(&ipc::endpoint_name(&args.datafile_path), true)
+            .unwrap()
+        {
+            Endpoint::New(listener) => listener,
+            Endpoint::Existing(stream) => {
+                forward_to_instance(stream, command, &args.datafile_path);
+                return;
+            }
+        };
+    // Without a running instance, commands are run right here, except for `open`,
+    // which needs the window
+    let open_cmd = match command {
+        Some(cmd @ Command::Open { .. }) => Some(cmd),
+        Some(cmd) => {
+            if let Err(e) = cli::run(&cmd, &args.datafile_path) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => None,
+    };
+    let native_options = eframe::NativeOptions {
+        viewport: ViewportBuilder::default().with_inner_size(egui::vec2(620., 480.)),
+        ..Default::default()
+    };
     eframe::run_native(
         "Simple egui todo",
         native_options,
         Box::new(|c_ctx| {
-            let egui_ctx = c_ctx.egui_ctx.clone();
-            std::thread::spawn(move || {
-                loop {
-                    if ipc_listener.accept().is_some() {
-                        egui_ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
-                        egui_ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
-                    }
-                    std::thread::sleep(Duration::from_millis(250));
-                }
-            });
+            let ipc_commands = ipc::spawn_listener(ipc_listener, c_ctx.egui_ctx.clone());
             c_ctx.egui_ctx.set_visuals(Visuals::dark());
             let mut app = match TodoApp::load(args.datafile_path.clone()) {
                 Ok(app) => app,
                 Err(e) => {
                     let msg = format!("Error loading .setodo.dat:\n{e}\n{e:?}");
-                    return Ok(Box::new(ErrorReport(msg)));
+                    return Ok(Box::new(RecoveryApp::new(
+                        msg,
+                        args.datafile_path,
+                        ipc_commands,
+                    )));
                 }
             };
-            let mut fonts = egui::FontDefinitions::default();
-            if let Some(stored) = &app.per.stored_font_data {
-                if let Err(e) =
-                    egui_fontcfg::load_custom_fonts(&stored.custom, &mut fonts.font_data)
-                {
-                    eprintln!("Failed to load custom fonts: {e}");
-                }
-                fonts.families = stored.families.clone();
+            app.temp.ipc_commands = Some(ipc_commands);
+            app.remember_recent_file();
+            if let Some(cmd) = open_cmd
+                && let Err(e) = cmd.execute(&mut app.per, &mut std::io::sink())
+            {
+                eprintln!("{e}");
             }
-            egui_phosphor::add_to_fonts(&mut fonts, egui_phosphor::Variant::Regular);
-            app.temp.font_defs_edit_copy = fonts.clone();
-            c_ctx.egui_ctx.set_fonts(fonts);
+            init_app(&c_ctx.egui_ctx, &mut app);
             Ok(Box::new(app))
         }),
     )
     .unwrap();
 }
 
-struct ErrorReport(String);
+/// Let the running instance run `command`, or just show its window if there is none
+fn forward_to_instance(stream: Stream, command: Option<Command>, data_file_path: &Path) {
+    let request = match command {
+        Some(cmd) => ipc::Request::Command {
+            data_file_path: ipc::canonical_path(data_file_path),
+            cmd,
+        },
+        None => ipc::Request::Show,
+    };
+    match ipc::send_request(stream, &request) {
+        Ok(output) => print!("{output}"),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
 
-impl eframe::App for ErrorReport {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Fatal error");
-            ui.separator();
-            ui.label(&self.0);
-            ui.separator();
-            ui.horizontal(|ui| {
-                if ui.button("Close").clicked() {
-                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-                }
-                if ui.button("Copy to clipboard").clicked() {
-                    ctx.copy_text(self.0.clone());
-                }
-            });
-        });
+/// Set up things that need both the egui context and the loaded app
+fn init_app(egui_ctx: &egui::Context, app: &mut TodoApp) {
+    let mut fonts = egui::FontDefinitions::default();
+    if let Some(stored) = &app.per.stored_font_data {
+        if let Err(e) = egui_fontcfg::load_custom_fonts(&stored.custom, &mut fonts.font_data) {
+            eprintln!("Failed to load custom fonts: {e}");
+        }
+        fonts.families = stored.families.clone();
     }
+    egui_phosphor::add_to_fonts(&mut fonts, egui_phosphor::Variant::Regular);
+    app.temp.font_defs_edit_copy = fonts.clone();
+    egui_ctx.set_fonts(fonts);
 }
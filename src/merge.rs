@@ -0,0 +1,115 @@
+use crate::{
+    data::{Entry, Id, Topic},
+    tree,
+};
+
+/// Merge the topic tree `theirs` into `ours`, matching topics and entries by id.
+///
+/// This is a union of both trees, so nothing gets lost:
+/// - Topics and entries only present in `theirs` are added, under the same parent topic if
+///   it exists in `ours`, otherwise at the top level.
+/// - If an entry exists on both sides with different content, both versions are kept, and
+///   their version is marked as a conflict.
+/// - For topics present on both sides, our name and description win.
+///
+/// A consequence is that things deleted on only one side come back.
+pub fn merge_topics(ours: &mut Vec<Topic>, theirs: Vec<Topic>) {
+    merge_inner(ours, theirs, None);
+}
+
+fn merge_inner(ours: &mut Vec<Topic>, theirs: Vec<Topic>, parent: Option<Id>) {
+    for mut their in theirs {
+        let their_children = std::mem::take(&mut their.children);
+        let their_entries = std::mem::take(&mut their.entries);
+        let id = their.id;
+        if tree::find_path(ours, |topic| topic.id == id).is_none() {
+            let parent_path = parent.and_then(|parent| tree::find_path(ours, |t| t.id == parent));
+            let siblings = match parent_path.and_then(|path| tree::get_mut(ours, &path)) {
+                Some(parent) => &mut parent.children,
+                None => &mut *ours,
+            };
+            their.task_sel = None;
+            siblings.push(their);
+        }
+        for entry in their_entries {
+            merge_entry(ours, id, entry);
+        }
+        merge_inner(ours, their_children, Some(id));
+    }
+}
+
+/// Merge their version of an entry, which belongs to the topic `topic_id` on their side
+fn merge_entry(ours: &mut [Topic], topic_id: Id, mut entry: Entry) {
+    let existing = tree::find_path(ours, |topic| topic.entry_pos(entry.id).is_some())
+        .and_then(|path| tree::get(ours, &path))
+        .and_then(|topic| topic.entries.iter().find(|en| en.id == entry.id));
+    match existing {
+        Some(existing) if *existing == entry => return,
+        Some(_) => {
+            entry.id = Id::new();
+            entry.title.push_str(" (conflict)");
+        }
+        None => {}
+    }
+    let Some(topic) = tree::find_path(ours, |topic| topic.id == topic_id)
+        .and_then(|path| tree::get_mut(ours, &path))
+    else {
+        return;
+    };
+    topic.entries.push(entry);
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::merge_topics,
+        crate::data::{Entry, Topic},
+    };
+
+    fn titles(topic: &Topic) -> Vec<&str> {
+        topic.entries.iter().map(|en| en.title.as_str()).collect()
+    }
+
+    #[test]
+    fn test_merge_adds_new_topics_and_entries() {
+        let mut base = Topic::new("a".into());
+        base.entries.push(Entry::new_task("shared".into()));
+        let mut ours = vec![base.clone()];
+        ours[0].entries.push(Entry::new_task("ours".into()));
+        let mut theirs = vec![base];
+        theirs[0].entries.push(Entry::new_task("theirs".into()));
+        theirs[0].children.push(Topic::new("a child".into()));
+        theirs.push(Topic::new("b".into()));
+        merge_topics(&mut ours, theirs);
+        assert_eq!(ours.len(), 2);
+        assert_eq!(titles(&ours[0]), ["shared", "ours", "theirs"]);
+        assert_eq!(ours[0].children[0].name, "a child");
+        assert_eq!(ours[1].name, "b");
+    }
+    #[test]
+    fn test_merge_keeps_both_conflicting_versions() {
+        let mut base = Topic::new("a".into());
+        base.entries.push(Entry::new_task("task".into()));
+        let mut ours = vec![base.clone()];
+        let mut theirs = vec![base];
+        ours[0].entries[0].desc = "our desc".into();
+        theirs[0].entries[0].desc = "their desc".into();
+        merge_topics(&mut ours, theirs);
+        assert_eq!(titles(&ours[0]), ["task", "task (conflict)"]);
+        assert_eq!(ours[0].entries[1].desc, "their desc");
+        assert_ne!(ours[0].entries[0].id, ours[0].entries[1].id);
+    }
+    #[test]
+    fn test_merge_follows_moved_entries() {
+        let mut a = Topic::new("a".into());
+        let b = Topic::new("b".into());
+        a.entries.push(Entry::new_task("task".into()));
+        let mut ours = vec![a.clone(), b.clone()];
+        // We moved the task from a to b
+        let task = ours[0].entries.remove(0);
+        ours[1].entries.push(task);
+        merge_topics(&mut ours, vec![a, b]);
+        assert!(ours[0].entries.is_empty());
+        assert_eq!(titles(&ours[1]), ["task"]);
+    }
+}
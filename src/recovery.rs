@@ -0,0 +1,266 @@
+use {
+    crate::{
+        app::{
+            ModalPayload, TodoApp, TodoAppPersistent, backup_path, existing_backups, read_header,
+        },
+        data::{Topic, assign_ids},
+        ipc::{self, IncomingCommands},
+    },
+    eframe::egui,
+    egui_file_dialog::FileDialog,
+    serde::de::{DeserializeSeed, Deserializer, IgnoredAny, SeqAccess, Visitor},
+    std::{
+        error::Error,
+        fs::File,
+        io::{BufReader, Read as _},
+        path::{Path, PathBuf},
+    },
+};
+
+pub struct RecoveryApp {
+    error: String,
+    data_file_path: PathBuf,
+    file_dialog: FileDialog,
+    /// Error message of the last failed recovery attempt
+    attempt_error: Option<String>,
+    /// Once recovery succeeded, everything is delegated to the recovered app
+    recovered: Option<TodoApp>,
+    /// Handed over to the recovered app
+    ipc_commands: Option<IncomingCommands>,
+}
+
+impl RecoveryApp {
+    pub fn new(error: String, data_file_path: PathBuf, ipc_commands: IncomingCommands) -> Self {
+        Self {
+            error,
+            data_file_path,
+            file_dialog: FileDialog::new(),
+            attempt_error: None,
+            recovered: None,
+            ipc_commands: Some(ipc_commands),
+        }
+    }
+
+    fn attempt(
+        &mut self,
+        ctx: &egui::Context,
+        f: impl FnOnce(&Path) -> Result<TodoApp, Box<dyn Error>>,
+    ) {
+        match f(&self.data_file_path) {
+            Ok(mut app) => {
+                crate::init_app(ctx, &mut app);
+                app.temp.ipc_commands = self.ipc_commands.take();
+                self.recovered = Some(app);
+            }
+            Err(e) => self.attempt_error = Some(e.to_string()),
+        }
+    }
+
+    fn recovery_ui(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Failed to load data file");
+        ui.separator();
+        ui.label(&self.error);
+        ui.horizontal(|ui| {
+            if ui.button("Close").clicked() {
+                ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            if ui.button("Copy to clipboard").clicked() {
+                ui.ctx().copy_text(self.error.clone());
+            }
+        });
+        ui.separator();
+        ui.heading("Recovery options");
+        let ctx = ui.ctx().clone();
+        let backups = existing_backups(&self.data_file_path);
+        if backups.is_empty() {
+            ui.label("There are no backups to restore from.");
+        }
+        for time in backups {
+            let path = backup_path(&self.data_file_path, time);
+            if ui
+                .button(format!(
+                    "Restore backup from {}",
+                    time.format("%Y-%m-%d %H:%M:%S")
+                ))
+                .on_hover_text(path.display().to_string())
+                .clicked()
+            {
+                self.attempt(&ctx, |data_file_path| {
+                    let per = TodoAppPersistent::read_from_file(&path)?;
+                    let mut app = TodoApp::new(per, data_file_path.to_owned());
+                    app.temp.per_dirty = true;
+                    Ok(app)
+                });
+            }
+        }
+        if ui.button("Open a different file...").clicked() {
+            self.file_dialog.pick_file();
+        }
+        if ui
+            .button("Start with a fresh file")
+            .on_hover_text("The broken file is renamed, not deleted")
+            .clicked()
+        {
+            self.attempt(&ctx, |data_file_path| {
+                let aside = set_aside_path(data_file_path);
+                std::fs::rename(data_file_path, &aside)?;
+                let mut app = TodoApp::load(data_file_path.to_owned())?;
+                app.temp.modal = Some(ModalPayload::InfoMsg(format!(
+                    "The broken file was moved to '{}'",
+                    aside.display()
+                )));
+                Ok(app)
+            });
+        }
+        if ui
+            .button("Salvage readable topics")
+            .on_hover_text("Load every top level topic that can still be decoded")
+            .clicked()
+        {
+            self.attempt(&ctx, |data_file_path| {
+                let topics = salvage_topics(data_file_path)?;
+                let n_topics = topics.len();
+                let per = TodoAppPersistent {
+                    topics,
+                    ..TodoAppPersistent::default()
+                };
+                let mut app = TodoApp::new(per, data_file_path.to_owned());
+                app.temp.per_dirty = true;
+                app.temp.modal = Some(ModalPayload::InfoMsg(format!(
+                    "Salvaged {n_topics} topic(s).\nSaving will overwrite the broken file, \
+                     but it will be kept as a backup."
+                )));
+                Ok(app)
+            });
+        }
+        if let Some(msg) = &self.attempt_error {
+            ui.separator();
+            ui.colored_label(egui::Color32::LIGHT_RED, format!("Recovery failed: {msg}"));
+        }
+    }
+}
+
+impl eframe::App for RecoveryApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        if let Some(app) = &mut self.recovered {
+            app.update(ctx, frame);
+            return;
+        }
+        if let Some(rx) = &self.ipc_commands {
+            for cmd in rx.try_iter() {
+                cmd.reply(&Err(
+                    "The running instance couldn't load its data file".into()
+                ));
+            }
+        }
+        egui::CentralPanel::default().show(ctx, |ui| self.recovery_ui(ui));
+        self.file_dialog.update(ctx);
+        if let Some(path) = self.file_dialog.take_picked() {
+            match ipc::claim_endpoint(&self.data_file_path, &path) {
+                Ok(listener) => {
+                    self.attempt(ctx, |_| TodoApp::load(path));
+                    if let Some(app) = &mut self.recovered
+                        && let Some(listener) = listener
+                    {
+                        app.temp.ipc_commands = Some(ipc::spawn_listener(listener, ctx.clone()));
+                    }
+                }
+                Err(e) => self.attempt_error = Some(e.to_string()),
+            }
+        }
+    }
+
+    fn on_exit(&mut self, gl: Option<&eframe::glow::Context>) {
+        if let Some(app) = &mut self.recovered {
+            app.on_exit(gl);
+        }
+    }
+}
+
+/// A path next to `path` that the broken file can be moved to
+fn set_aside_path(path: &Path) -> PathBuf {
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(
+        ".broken-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    aside.into()
+}
+
+/// Decode as many top level topics as possible from a damaged data file.
+///
+/// Decoding stops at the first topic that can't be read, so this mostly helps with
+/// truncated files.
+fn salvage_topics(path: &Path) -> Result<Vec<Topic>, Box<dyn Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    read_header(&mut reader)?;
+    let mut dec = zstd::stream::read::Decoder::with_buffer(reader)?;
+    let mut bytes = Vec::new();
+    // Whatever could be decompressed before the error is still in `bytes`
+    if let Err(e) = dec.read_to_end(&mut bytes) {
+        eprintln!("Salvage: decompression stopped early: {e}");
+    }
+    let mut topics = Vec::new();
+    let mut de = rmp_serde::Deserializer::new(bytes.as_slice());
+    if let Err(e) = SalvageSeed(&mut topics).deserialize(&mut de) {
+        eprintln!("Salvage: decoding stopped early: {e}");
+    }
+    if topics.is_empty() {
+        return Err("No topics could be salvaged".into());
+    }
+    assign_ids(&mut topics);
+    Ok(topics)
+}
+
+/// Reads the `topics` field of [`TodoAppPersistent`], collecting topics as it goes.
+///
+/// The topics are pushed into the borrowed vector, so they survive a decoding error.
+struct SalvageSeed<'a>(&'a mut Vec<Topic>);
+
+impl<'de> DeserializeSeed<'de> for SalvageSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SalvageSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("setodo persistent data")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        // topic_sel
+        seq.next_element::<IgnoredAny>()?;
+        seq.next_element_seed(TopicsSeed(self.0))?;
+        Ok(())
+    }
+}
+
+struct TopicsSeed<'a>(&'a mut Vec<Topic>);
+
+impl<'de> DeserializeSeed<'de> for TopicsSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TopicsSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a list of topics")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(topic) = seq.next_element()? {
+            self.0.push(topic);
+        }
+        Ok(())
+    }
+}
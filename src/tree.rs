@@ -1,4 +1,5 @@
 pub trait Node: Sized {
+    fn children(&self) -> &[Self];
     fn children_mut(&mut self) -> &mut Vec<Self>;
 }
 
@@ -25,6 +26,35 @@ pub fn move_<T: Node>(nodes: &mut Vec<T>, src_idx: &[usize], dst_idx: &[usize])
     }
 }
 
+pub fn get<'t, T: Node>(mut nodes: &'t [T], indices: &[usize]) -> Option<&'t T> {
+    let (last, init) = indices.split_last()?;
+    for &idx in init {
+        nodes = nodes.get(idx)?.children();
+    }
+    nodes.get(*last)
+}
+
+/// Find the index path of the first node (in depth-first order) that matches `pred`
+pub fn find_path<T: Node>(nodes: &[T], mut pred: impl FnMut(&T) -> bool) -> Option<Vec<usize>> {
+    let mut path = Vec::new();
+    find_path_inner(nodes, &mut pred, &mut path).then_some(path)
+}
+
+fn find_path_inner<T: Node>(
+    nodes: &[T],
+    pred: &mut impl FnMut(&T) -> bool,
+    path: &mut Vec<usize>,
+) -> bool {
+    for (i, node) in nodes.iter().enumerate() {
+        path.push(i);
+        if pred(node) || find_path_inner(node.children(), pred, path) {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
 pub fn get_mut<'t, T: Node>(mut nodes: &'t mut [T], indices: &[usize]) -> Option<&'t mut T> {
     for i in 0..indices.len() {
         let idx = *indices.get(i)?;
@@ -56,13 +86,68 @@ pub fn insert<T: Node>(mut nodes: &mut Vec<T>, indices: &[usize], node: T) {
     nodes.push(node);
 }
 
+/// The children of the node at `parent_idx`, or `nodes` itself if it's empty
+pub fn children_mut<'t, T: Node>(
+    nodes: &'t mut Vec<T>,
+    parent_idx: &[usize],
+) -> Option<&'t mut Vec<T>> {
+    if parent_idx.is_empty() {
+        return Some(nodes);
+    }
+    get_mut(nodes, parent_idx).map(T::children_mut)
+}
+
+/// Insert `node` as the child of `parent_idx` at position `pos`.
+///
+/// `pos` is clamped to the number of children, so `usize::MAX` appends.
+pub fn insert_at<T: Node>(nodes: &mut Vec<T>, parent_idx: &[usize], pos: usize, node: T) {
+    if let Some(children) = children_mut(nodes, parent_idx) {
+        children.insert(pos.min(children.len()), node);
+    }
+}
+
+/// Move the node at `src_idx` to position `pos` among the children of `dst_parent_idx`.
+///
+/// `dst_parent_idx` and `pos` are where it should go in the tree as it is before the move,
+/// so moving a node to right after its next sibling means `pos = src + 2`.
+pub fn move_to<T: Node>(
+    nodes: &mut Vec<T>,
+    src_idx: &[usize],
+    dst_parent_idx: &[usize],
+    pos: usize,
+) {
+    if src_idx.is_empty() || !move_precondition(src_idx, dst_parent_idx) {
+        return;
+    }
+    let mut dst_idx = [dst_parent_idx, &[pos]].concat();
+    // Removing the src node shifts its later siblings (and what's inside them) back by one
+    let level = src_idx.len() - 1;
+    if dst_idx.len() > level
+        && dst_idx[..level] == src_idx[..level]
+        && dst_idx[level] > src_idx[level]
+    {
+        dst_idx[level] -= 1;
+    }
+    // Make sure there's somewhere to put it before taking it out
+    let (&pos, parent_idx) = dst_idx.split_last().unwrap();
+    if get(nodes, src_idx).is_none() || children_mut(nodes, dst_parent_idx).is_none() {
+        return;
+    }
+    if let Some(node) = remove(nodes, src_idx) {
+        insert_at(nodes, parent_idx, pos, node);
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::move_;
+    use super::{find_path, insert_at, move_, move_to};
 
     #[derive(PartialEq, Debug)]
     struct N(&'static str, Vec<Self>);
     impl super::Node for N {
+        fn children(&self) -> &[Self] {
+            &self.1
+        }
         fn children_mut(&mut self) -> &mut Vec<Self> {
             &mut self.1
         }
@@ -85,4 +170,83 @@ mod test {
             vec![N("b", vec![N("b1", vec![]), N("a", vec![N("a1", vec![])])])]
         );
     }
+    #[test]
+    fn test_find_path() {
+        let nodes = vec![
+            N("a", vec![N("a1", vec![])]),
+            N("b", vec![N("b1", vec![N("b1x", vec![])])]),
+        ];
+        assert_eq!(find_path(&nodes, |n| n.0 == "b1x"), Some(vec![1, 0, 0]));
+        assert_eq!(find_path(&nodes, |n| n.0 == "a"), Some(vec![0]));
+        assert_eq!(find_path(&nodes, |n| n.0 == "c"), None);
+    }
+    #[test]
+    fn test_insert_at() {
+        let mut nodes = vec![N("a", vec![N("a1", vec![])])];
+        insert_at(&mut nodes, &[0], 0, N("a0", vec![]));
+        insert_at(&mut nodes, &[], usize::MAX, N("b", vec![]));
+        assert_eq!(
+            nodes,
+            vec![
+                N("a", vec![N("a0", vec![]), N("a1", vec![])]),
+                N("b", vec![])
+            ]
+        );
+    }
+    #[test]
+    fn test_move_to_between_siblings() {
+        let mut nodes = vec![N("a", vec![]), N("b", vec![]), N("c", vec![])];
+        // After c
+        move_to(&mut nodes, &[0], &[], 3);
+        assert_eq!(nodes, vec![N("b", vec![]), N("c", vec![]), N("a", vec![])]);
+        // Before b
+        move_to(&mut nodes, &[1], &[], 0);
+        assert_eq!(nodes, vec![N("c", vec![]), N("b", vec![]), N("a", vec![])]);
+        // To where it already is
+        move_to(&mut nodes, &[1], &[], 2);
+        assert_eq!(nodes, vec![N("c", vec![]), N("b", vec![]), N("a", vec![])]);
+    }
+    #[test]
+    fn test_move_to_other_level() {
+        let mut nodes = vec![
+            N("a", vec![N("a1", vec![])]),
+            N("b", vec![N("b1", vec![]), N("b2", vec![])]),
+        ];
+        // Between b1 and b2, which shift when a is removed
+        move_to(&mut nodes, &[0], &[1], 1);
+        assert_eq!(
+            nodes,
+            vec![N(
+                "b",
+                vec![
+                    N("b1", vec![]),
+                    N("a", vec![N("a1", vec![])]),
+                    N("b2", vec![])
+                ]
+            )]
+        );
+        // Out to the root, before b
+        move_to(&mut nodes, &[0, 2], &[], 0);
+        assert_eq!(
+            nodes,
+            vec![
+                N("b2", vec![]),
+                N("b", vec![N("b1", vec![]), N("a", vec![N("a1", vec![])])])
+            ]
+        );
+    }
+    #[test]
+    fn test_move_to_nonexistent_parent() {
+        let mut nodes = vec![N("a", vec![]), N("b", vec![])];
+        move_to(&mut nodes, &[0], &[5], 0);
+        move_to(&mut nodes, &[0], &[1, 0], 0);
+        assert_eq!(nodes, vec![N("a", vec![]), N("b", vec![])]);
+    }
+    #[test]
+    fn test_move_to_inside_itself() {
+        let mut nodes = vec![N("a", vec![N("a1", vec![])]), N("b", vec![])];
+        move_to(&mut nodes, &[0], &[0], 0);
+        move_to(&mut nodes, &[0], &[0, 0], 0);
+        assert_eq!(nodes, vec![N("a", vec![N("a1", vec![])]), N("b", vec![])]);
+    }
 }
@@ -1,2 +1,4 @@
+pub mod agenda;
 pub mod central_panel;
+pub mod preferences;
 pub mod tree_view;
@@ -0,0 +1,156 @@
+use {
+    crate::{
+        app::{TodoApp, UiState},
+        data::{EntryKind, Topic},
+        tree,
+    },
+    chrono::NaiveDate,
+    eframe::egui,
+    egui_phosphor::regular as ph,
+};
+
+/// How many days ahead the "Upcoming" section looks
+const UPCOMING_DAYS: i64 = 7;
+
+pub fn ui(ui: &mut egui::Ui, app: &mut TodoApp) {
+    if ui.link("Back").clicked() {
+        app.temp.state = UiState::Normal;
+    }
+    ui.separator();
+    ui.heading(format!("{} Agenda", ph::CALENDAR));
+    let today = chrono::Local::now().date_naive();
+    let items = collect_items(&app.per.topics, today);
+    if items.is_empty() {
+        ui.label("Nothing due. Enjoy your free time!");
+        return;
+    }
+    let mut goto = None;
+    egui::ScrollArea::vertical()
+        .auto_shrink(false)
+        .id_salt("agenda_scroll")
+        .show(ui, |ui| {
+            for section in [Section::Overdue, Section::Today, Section::Upcoming] {
+                let mut section_items = items.iter().filter(|item| item.section == section);
+                let Some(first) = section_items.next() else {
+                    continue;
+                };
+                ui.add_space(4.0);
+                ui.label(
+                    egui::RichText::new(section.label())
+                        .strong()
+                        .color(section.color()),
+                );
+                let mut prev_topic: Option<&[usize]> = None;
+                for item in std::iter::once(first).chain(section_items) {
+                    if prev_topic != Some(&item.topic_path) {
+                        ui.label(&item.topic_label);
+                        prev_topic = Some(&item.topic_path);
+                    }
+                    ui.indent(("agenda_indent", &item.topic_path), |ui| {
+                        ui.horizontal(|ui| {
+                            if ui.link(&item.title).clicked() {
+                                goto = Some((item.topic_path.clone(), item.entry_idx));
+                            }
+                            ui.weak(item.date.to_string());
+                        });
+                    });
+                }
+            }
+        });
+    if let Some((topic_path, entry_idx)) = goto
+        && let Some(topic) = tree::get_mut(&mut app.per.topics, &topic_path)
+    {
+        topic.select_entry(entry_idx);
+        app.per.topic_sel = topic_path;
+        app.temp.state = UiState::Normal;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Section {
+    Overdue,
+    Today,
+    Upcoming,
+}
+
+impl Section {
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Overdue => "Overdue",
+            Self::Today => "Today",
+            Self::Upcoming => "Upcoming",
+        }
+    }
+    const fn color(self) -> egui::Color32 {
+        match self {
+            Self::Overdue => egui::Color32::LIGHT_RED,
+            Self::Today => egui::Color32::ORANGE,
+            Self::Upcoming => egui::Color32::YELLOW,
+        }
+    }
+}
+
+struct AgendaItem {
+    section: Section,
+    date: NaiveDate,
+    topic_path: Vec<usize>,
+    /// Names of the topics leading to this item, joined with " / "
+    topic_label: String,
+    entry_idx: usize,
+    title: String,
+}
+
+/// Collect all unfinished entries that are overdue, due today, or due in the near future.
+///
+/// Entries without a due date are considered by their scheduled date instead.
+/// The result is sorted by section, then by topic, then by date.
+fn collect_items(topics: &[Topic], today: NaiveDate) -> Vec<AgendaItem> {
+    let mut items = Vec::new();
+    collect_items_inner(topics, today, &mut Vec::new(), &mut Vec::new(), &mut items);
+    items.sort_by(|a, b| {
+        a.section
+            .cmp(&b.section)
+            .then_with(|| a.topic_path.cmp(&b.topic_path))
+            .then_with(|| a.date.cmp(&b.date))
+    });
+    items
+}
+
+fn collect_items_inner<'t>(
+    topics: &'t [Topic],
+    today: NaiveDate,
+    cursor: &mut Vec<usize>,
+    names: &mut Vec<&'t str>,
+    items: &mut Vec<AgendaItem>,
+) {
+    for (i, topic) in topics.iter().enumerate() {
+        cursor.push(i);
+        names.push(&topic.name);
+        for (en_idx, en) in topic.entries.iter().enumerate() {
+            // Like `Entry::due_status`, only open tasks are due
+            if en.kind != EntryKind::Task || en.done {
+                continue;
+            }
+            let Some(date) = en.due.or(en.scheduled) else {
+                continue;
+            };
+            let section = match (date - today).num_days() {
+                ..0 => Section::Overdue,
+                0 => Section::Today,
+                1..=UPCOMING_DAYS => Section::Upcoming,
+                _ => continue,
+            };
+            items.push(AgendaItem {
+                section,
+                date,
+                topic_path: cursor.clone(),
+                topic_label: names.join(" / "),
+                entry_idx: en_idx,
+                title: en.title.clone(),
+            });
+        }
+        collect_items_inner(&topic.children, today, cursor, names, items);
+        names.pop();
+        cursor.pop();
+    }
+}
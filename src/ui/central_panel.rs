@@ -2,19 +2,36 @@ use {
     crate::{
         app::{ConfirmAction, ModalPayload, StoredFontData, TodoApp, TodoAppTemp, UiState},
         cmd::Cmd,
-        data::{Attachment, Entry, EntryKind, Topic},
+        data::{Attachment, DueStatus, Entry, EntryKind, Id, Recurrence, Topic},
         tree,
     },
-    eframe::egui::{self, KeyboardShortcut, TextBuffer as _},
+    chrono::NaiveDate,
+    constcat::concat as cc,
+    eframe::egui::{
+        self, KeyboardShortcut, TextBuffer as _,
+        collapsing_header::{CollapsingState, paint_default_icon},
+    },
     egui_commonmark::CommonMarkViewer,
+    egui_extras::DatePickerButton,
     egui_fontcfg::FontDefsUiMsg,
     egui_phosphor::regular as ph,
 };
 
 pub fn ui(ui: &mut egui::Ui, app: &mut TodoApp) {
-    if matches!(app.temp.state, UiState::FontCfg) {
-        font_defs_ui(ui, app);
-        return;
+    match app.temp.state {
+        UiState::FontCfg => {
+            font_defs_ui(ui, app);
+            return;
+        }
+        UiState::Agenda => {
+            crate::ui::agenda::ui(ui, app);
+            return;
+        }
+        UiState::Preferences => {
+            crate::ui::preferences::ui(ui, app);
+            return;
+        }
+        _ => {}
     }
     let cp_avail_height = ui.available_height();
     ui.horizontal(|ui| {
@@ -31,45 +48,7 @@ pub fn ui(ui: &mut egui::Ui, app: &mut TodoApp) {
                     ));
                     return;
                 };
-                ui.horizontal(|ui| {
-                    ui.heading(&topic.name);
-                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                        if let UiState::EditTopicDesc = app.temp.state {
-                            if ui
-                                .button(ph::STOP_CIRCLE)
-                                .on_hover_text("Stop editing")
-                                .clicked()
-                            {
-                                app.temp.state = UiState::Normal;
-                            }
-                        } else {
-                            if ui
-                                .button(ph::TRASH)
-                                .on_hover_text("Clear topic entries")
-                                .clicked()
-                            {
-                                app.temp.confirm_action = Some(ConfirmAction::ClearTopicEntries);
-                            }
-                            if ui
-                                .button(egui_phosphor::regular::PENCIL)
-                                .on_hover_text("Edit description")
-                                .clicked()
-                            {
-                                app.temp.state = UiState::EditTopicDesc;
-                            }
-                            if ui
-                                .button(egui_phosphor::regular::CURSOR_TEXT)
-                                .on_hover_text("Edit title")
-                                .clicked()
-                            {
-                                app.temp.state = UiState::RenameTopic {
-                                    idx: app.per.topic_sel.clone(),
-                                };
-                                app.temp.cmd.push(Cmd::FocusTextEdit);
-                            }
-                        }
-                    });
-                });
+                topic_header_ui(ui, &mut app.temp, topic);
                 match app.temp.state {
                     UiState::EditTopicDesc => {
                         ui.text_edit_multiline(&mut topic.desc);
@@ -81,7 +60,12 @@ pub fn ui(ui: &mut egui::Ui, app: &mut TodoApp) {
                     }
                 }
                 ui.separator();
-                tasks_list_ui(ui, &mut app.temp, topic, &app.per.topic_sel);
+                tasks_list_ui(
+                    ui,
+                    &mut app.temp,
+                    topic,
+                    app.per.settings.auto_complete_parents,
+                );
                 if let Some(sel) = topic.task_sel
                     && let Some(en) = topic.entries.get_mut(sel)
                 {
@@ -96,6 +80,9 @@ pub fn ui(ui: &mut egui::Ui, app: &mut TodoApp) {
                                     topic.task_sel = Some(pos);
                                 }
                             }
+                            TaskUiCmd::GotoEntryId { id } => {
+                                app.temp.cmd.push(Cmd::GotoEntry { id });
+                            }
                         }
                     }
                 }
@@ -104,6 +91,46 @@ pub fn ui(ui: &mut egui::Ui, app: &mut TodoApp) {
     });
 }
 
+fn topic_header_ui(ui: &mut egui::Ui, app_temp: &mut TodoAppTemp, topic: &Topic) {
+    ui.horizontal(|ui| {
+        ui.heading(&topic.name);
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            if let UiState::EditTopicDesc = app_temp.state {
+                if ui
+                    .button(ph::STOP_CIRCLE)
+                    .on_hover_text("Stop editing")
+                    .clicked()
+                {
+                    app_temp.state = UiState::Normal;
+                }
+            } else {
+                if ui
+                    .button(ph::TRASH)
+                    .on_hover_text("Clear topic entries")
+                    .clicked()
+                {
+                    app_temp.confirm_action = Some(ConfirmAction::ClearTopicEntries);
+                }
+                if ui
+                    .button(egui_phosphor::regular::PENCIL)
+                    .on_hover_text("Edit description")
+                    .clicked()
+                {
+                    app_temp.state = UiState::EditTopicDesc;
+                }
+                if ui
+                    .button(egui_phosphor::regular::CURSOR_TEXT)
+                    .on_hover_text("Edit title")
+                    .clicked()
+                {
+                    app_temp.state = UiState::RenameTopic { id: topic.id };
+                    app_temp.cmd.push(Cmd::FocusTextEdit);
+                }
+            }
+        });
+    });
+}
+
 fn font_defs_ui(ui: &mut egui::Ui, app: &mut TodoApp) {
     if ui.link("Back").clicked() {
         app.temp.state = UiState::Normal;
@@ -125,55 +152,75 @@ fn tasks_list_ui(
     ui: &mut egui::Ui,
     app_temp: &mut TodoAppTemp,
     topic: &mut Topic,
-    topic_sel: &[usize],
+    auto_complete: bool,
 ) {
+    let today = chrono::Local::now().date_naive();
+    let mut dropped = None;
+    let mut clicked = None;
+    let selected = topic.selected_entry_positions();
+    let pad = topic.entries.iter().any(|en| !en.children.is_empty());
     egui::ScrollArea::vertical()
         .auto_shrink([false; 2])
         .id_salt("tasks_scroll")
         .max_height(200.0)
         .show(ui, |ui| {
             for (i, entry) in topic.entries.iter_mut().enumerate() {
-                ui.horizontal(|ui| {
-                    match entry.kind {
-                        EntryKind::Task => {
-                            let re = ui.checkbox(&mut entry.done, "");
-                            if re.changed() {
-                                app_temp.per_dirty = true;
-                            }
+                let mut cs = subtasks_collapsing_state(ui.ctx(), entry.id);
+                let row = ui.horizontal(|ui| {
+                    subtasks_toggle_ui(ui, &mut cs, entry, pad);
+                    if entry_checkbox_ui(ui, entry, today) {
+                        app_temp.per_dirty = true;
+                    }
+                    if is_renaming(app_temp, topic.id, entry.id) {
+                        rename_entry_ui(ui, app_temp, entry);
+                    } else {
+                        let re = ui
+                            .selectable_label(selected.contains(&i), entry_title_text(entry, today))
+                            .interact(egui::Sense::drag());
+                        if re.clicked() {
+                            clicked = Some((i, ui.input(|inp| inp.modifiers)));
                         }
-                        EntryKind::Info => {
-                            ui.label("ℹ");
+                        re.dnd_set_drag_payload(DraggedEntry { id: entry.id });
+                        if let Some(entry_drop) = entry_drop_ui(ui, &re, i) {
+                            dropped = Some(entry_drop);
                         }
-                    }
-                    let mut text = egui::RichText::new(&entry.title);
-                    if entry.done {
-                        text = text.strikethrough();
-                    }
-                    match &app_temp.state {
-                        UiState::RenameTask {
-                            task_idx,
-                            topic_idx,
-                        } if topic_idx == topic_sel && i == *task_idx => {
-                            if ui.text_edit_singleline(&mut entry.title).lost_focus() {
-                                app_temp.state = UiState::Normal;
-                            }
+                        if re.double_clicked() {
+                            app_temp.state = UiState::RenameTask {
+                                topic_id: topic.id,
+                                task_id: entry.id,
+                            };
                         }
-                        _ => {
-                            let re = ui.selectable_label(topic.task_sel == Some(i), text);
-                            if re.clicked() {
-                                topic.task_sel = Some(i);
+                        re.context_menu(|ui| {
+                            if ui.button(cc!(ph::LIST_PLUS, " Add subtask")).clicked() {
+                                add_subtask(ui.ctx(), app_temp, topic.id, entry);
                             }
-                            if re.double_clicked() {
-                                app_temp.state = UiState::RenameTask {
-                                    topic_idx: topic_sel.to_vec(),
-                                    task_idx: topic.task_sel.unwrap(),
-                                };
-                            }
-                        }
+                        });
                     }
+                    entry_badges_ui(ui, entry);
                 });
+                if !entry.children.is_empty() {
+                    cs.show_body_indented(&row.response, ui, |ui| {
+                        if subtasks_ui(ui, &mut entry.children, app_temp, topic.id, today) {
+                            app_temp.per_dirty = true;
+                            if auto_complete {
+                                entry.complete_if_subtasks_done(today);
+                            }
+                        }
+                    });
+                }
             }
         });
+    match clicked {
+        Some((i, modifiers)) if modifiers.shift => topic.select_entry_range(i),
+        Some((i, modifiers)) if modifiers.command => topic.toggle_entry_sel(i),
+        Some((i, _)) => topic.select_entry(i),
+        None => {}
+    }
+    if let Some((id, pos)) = dropped
+        && let Some(src) = topic.entry_pos(id)
+    {
+        topic.move_entry(src, pos);
+    }
     ui.separator();
     ui.horizontal(|ui| {
         if let UiState::AddTask(name) = &mut app_temp.state {
@@ -185,27 +232,8 @@ fn tasks_list_ui(
             } else {
                 ui.text_edit_singleline(name).request_focus();
                 if clicked || ui.input(|inp| inp.key_pressed(egui::Key::Enter)) {
-                    topic.entries.insert(
-                        topic.task_sel.map_or(0, |idx| idx + 1),
-                        Entry {
-                            title: name.take(),
-                            desc: String::new(),
-                            done: false,
-                            attachments: Vec::new(),
-                            kind: EntryKind::Task,
-                        },
-                    );
+                    topic.insert_task_after_sel(Entry::new_task(name.take()));
                     app_temp.state = UiState::Normal;
-                    match &mut topic.task_sel {
-                        Some(sel) => {
-                            if *sel + 1 < topic.entries.len() {
-                                *sel += 1;
-                            }
-                        }
-                        None => {
-                            topic.task_sel = Some(0);
-                        }
-                    }
                 }
             }
         } else {
@@ -214,6 +242,183 @@ fn tasks_list_ui(
     });
 }
 
+/// The subtasks of an entry, indented below it. Returns whether any of them changed.
+fn subtasks_ui(
+    ui: &mut egui::Ui,
+    entries: &mut Vec<Entry>,
+    app_temp: &mut TodoAppTemp,
+    topic_id: Id,
+    today: NaiveDate,
+) -> bool {
+    let mut changed = false;
+    let mut remove = None;
+    let pad = entries.iter().any(|en| !en.children.is_empty());
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let mut cs = subtasks_collapsing_state(ui.ctx(), entry.id);
+        let row = ui.horizontal(|ui| {
+            subtasks_toggle_ui(ui, &mut cs, entry, pad);
+            changed |= entry_checkbox_ui(ui, entry, today);
+            if is_renaming(app_temp, topic_id, entry.id) {
+                rename_entry_ui(ui, app_temp, entry);
+            } else {
+                let re = ui.add(
+                    egui::Label::new(entry_title_text(entry, today)).sense(egui::Sense::click()),
+                );
+                if re.double_clicked() {
+                    app_temp.state = UiState::RenameTask {
+                        topic_id,
+                        task_id: entry.id,
+                    };
+                    app_temp.cmd.push(Cmd::FocusTextEdit);
+                }
+                re.context_menu(|ui| {
+                    if ui.button(cc!(ph::LIST_PLUS, " Add subtask")).clicked() {
+                        add_subtask(ui.ctx(), app_temp, topic_id, entry);
+                    }
+                    if ui.button(cc!(ph::TRASH, " Delete")).clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            entry_badges_ui(ui, entry);
+        });
+        if !entry.children.is_empty() {
+            cs.show_body_indented(&row.response, ui, |ui| {
+                changed |= subtasks_ui(ui, &mut entry.children, app_temp, topic_id, today);
+            });
+        }
+    }
+    if let Some(i) = remove {
+        entries.remove(i);
+        changed = true;
+    }
+    changed
+}
+
+fn subtasks_collapsing_state(ctx: &egui::Context, entry_id: Id) -> CollapsingState {
+    let id = egui::Id::new("subtasks").with(entry_id);
+    CollapsingState::load_with_default_open(ctx, id, true)
+}
+
+/// Button to collapse the subtasks of `entry`.
+///
+/// Entries without subtasks get the same amount of space instead if `pad` is set,
+/// so they line up with their siblings that have some.
+fn subtasks_toggle_ui(ui: &mut egui::Ui, cs: &mut CollapsingState, entry: &Entry, pad: bool) {
+    if !entry.children.is_empty() {
+        cs.show_toggle_button(ui, paint_default_icon);
+    } else if pad {
+        ui.add_space(ui.spacing().icon_width);
+    }
+}
+
+/// Done checkbox of a task, or an icon for other kinds. Returns whether it was toggled.
+fn entry_checkbox_ui(ui: &mut egui::Ui, entry: &mut Entry, today: NaiveDate) -> bool {
+    match entry.kind {
+        EntryKind::Task => {
+            let changed = ui.checkbox(&mut entry.done, "").changed();
+            if changed && entry.done {
+                entry.roll_forward(today);
+            }
+            changed
+        }
+        EntryKind::Info => {
+            ui.label("ℹ");
+            false
+        }
+    }
+}
+
+fn entry_title_text(entry: &Entry, today: NaiveDate) -> egui::RichText {
+    let mut text = egui::RichText::new(&entry.title);
+    if entry.done {
+        text = text.strikethrough();
+    }
+    if let Some(status) = entry.due_status(today) {
+        text = text.color(status.color());
+    }
+    text
+}
+
+fn is_renaming(app_temp: &TodoAppTemp, topic_id: Id, entry_id: Id) -> bool {
+    matches!(
+        app_temp.state,
+        UiState::RenameTask { topic_id: t, task_id } if t == topic_id && task_id == entry_id
+    )
+}
+
+fn rename_entry_ui(ui: &mut egui::Ui, app_temp: &mut TodoAppTemp, entry: &mut Entry) {
+    let re = ui.text_edit_singleline(&mut entry.title);
+    let mut focus_me = false;
+    app_temp.cmd.retain(|cmd| {
+        if let Cmd::FocusTextEdit = cmd {
+            focus_me = true;
+            false
+        } else {
+            true
+        }
+    });
+    if focus_me {
+        re.request_focus();
+    }
+    if re.lost_focus() {
+        app_temp.state = UiState::Normal;
+    }
+}
+
+/// Due date, repeat and subtask progress indicators shown after the title
+fn entry_badges_ui(ui: &mut egui::Ui, entry: &Entry) {
+    if let Some(due) = entry.due
+        && !entry.done
+    {
+        ui.weak(format!("{} {due}", ph::CALENDAR_BLANK));
+    }
+    if entry.recurrence.is_some() {
+        ui.weak(ph::REPEAT);
+    }
+    if let Some((done, total)) = entry.subtask_progress() {
+        ui.weak(format!("{done}/{total}"));
+    }
+}
+
+/// Add an empty subtask to `parent` and start renaming it
+fn add_subtask(ctx: &egui::Context, app_temp: &mut TodoAppTemp, topic_id: Id, parent: &mut Entry) {
+    let child = Entry::new_task(String::new());
+    app_temp.state = UiState::RenameTask {
+        topic_id,
+        task_id: child.id,
+    };
+    app_temp.cmd.push(Cmd::FocusTextEdit);
+    app_temp.per_dirty = true;
+    parent.children.push(child);
+    // Make sure the new subtask is visible to be renamed
+    let mut cs = subtasks_collapsing_state(ctx, parent.id);
+    cs.set_open(true);
+    cs.store(ctx);
+}
+
+/// Payload of an entry being dragged
+pub struct DraggedEntry {
+    pub id: Id,
+}
+
+/// Let entries be dropped on the label of the entry at `idx`, before or after it.
+///
+/// Returns the id of the dropped entry, and the position it should go to.
+fn entry_drop_ui(ui: &egui::Ui, re: &egui::Response, idx: usize) -> Option<(Id, usize)> {
+    re.dnd_hover_payload::<DraggedEntry>()?;
+    let pointer_pos = ui.ctx().pointer_latest_pos()?;
+    let (y, pos) = if pointer_pos.y < re.rect.center().y {
+        (re.rect.top(), idx)
+    } else {
+        (re.rect.bottom(), idx + 1)
+    };
+    ui.painter()
+        .hline(re.rect.x_range(), y, ui.visuals().selection.stroke);
+    re.dnd_release_payload::<DraggedEntry>()
+        .map(|dragged| (dragged.id, pos))
+}
+
 const ADD_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(egui::Modifiers::CTRL, egui::Key::N);
 const DEL_SHORTCUT: KeyboardShortcut =
     KeyboardShortcut::new(egui::Modifiers::CTRL, egui::Key::Delete);
@@ -234,22 +439,20 @@ fn tasks_list_bottom_bar_default_ui(
     {
         app_temp.state = UiState::add_task();
     }
-    if (ui
+    if ui
         .button(ph::TRASH)
         .on_hover_text(format!(
-            "Delete selected entry ({})",
+            "Delete selected entries ({})",
             ui.ctx().format_shortcut(&DEL_SHORTCUT)
         ))
         .clicked()
-        || ui.input_mut(|inp| inp.consume_shortcut(&DEL_SHORTCUT)))
-        && let Some(task_sel) = topic.task_sel
+        || ui.input_mut(|inp| inp.consume_shortcut(&DEL_SHORTCUT))
     {
-        topic.entries.remove(task_sel);
-        if topic.entries.is_empty() {
-            topic.task_sel = None;
-        } else {
-            topic.task_sel = Some(task_sel.clamp(0, topic.entries.len() - 1));
-        }
+        topic.take_selected_entries();
+    }
+    if topic.selected_entry_positions().len() > 1 {
+        bulk_actions_ui(app_temp, topic, ui);
+        return;
     }
     if let Some(task_sel) = topic.task_sel {
         if ui
@@ -274,17 +477,25 @@ fn tasks_list_bottom_bar_default_ui(
             .on_hover_text("Auto sort")
             .clicked()
         {
+            let sel_id = topic.entries[task_sel].id;
             topic
                 .entries
                 .sort_by(|a, b| a.done.cmp(&b.done).then_with(|| a.title.cmp(&b.title)));
+            topic.task_sel = topic.entry_pos(sel_id);
+        }
+        if ui
+            .button(ph::LIST_PLUS)
+            .on_hover_text("Add subtask")
+            .clicked()
+        {
+            add_subtask(ui.ctx(), app_temp, topic.id, &mut topic.entries[task_sel]);
         }
         if ui
             .button("⬈ Move")
-            .on_hover_text("Move into another topic")
+            .on_hover_text("Move into another topic\nEntries can also be dragged onto topics")
             .clicked()
         {
-            app_temp.state = UiState::MoveTaskIntoTopic(topic.entries.remove(task_sel));
-            topic.task_sel = None;
+            app_temp.state = UiState::MoveTaskIntoTopic(vec![topic.entries[task_sel].id]);
         }
         let Some(entry) = topic.entries.get_mut(task_sel) else {
             ui.label("<error getting entry>");
@@ -299,6 +510,74 @@ fn tasks_list_bottom_bar_default_ui(
     }
 }
 
+/// Actions for when more than one entry is selected
+fn bulk_actions_ui(app_temp: &mut TodoAppTemp, topic: &mut Topic, ui: &mut egui::Ui) {
+    let positions = topic.selected_entry_positions();
+    ui.label(format!("{} selected", positions.len()));
+    if ui
+        .button(ph::CHECK_SQUARE)
+        .on_hover_text("Mark as done")
+        .clicked()
+    {
+        let today = chrono::Local::now().date_naive();
+        for &i in &positions {
+            let entry = &mut topic.entries[i];
+            if entry.kind == EntryKind::Task && !entry.done {
+                entry.done = true;
+                entry.roll_forward(today);
+            }
+        }
+    }
+    if ui
+        .button(ph::SQUARE)
+        .on_hover_text("Mark as not done")
+        .clicked()
+    {
+        for &i in &positions {
+            topic.entries[i].done = false;
+        }
+    }
+    if ui.button(ph::COPY).on_hover_text("Copy titles").clicked() {
+        let titles: Vec<&str> = positions
+            .iter()
+            .map(|&i| topic.entries[i].title.as_str())
+            .collect();
+        ui.ctx().copy_text(titles.join("\n"));
+    }
+    if ui
+        .button("⬈ Move")
+        .on_hover_text("Move into another topic")
+        .clicked()
+    {
+        app_temp.state =
+            UiState::MoveTaskIntoTopic(positions.iter().map(|&i| topic.entries[i].id).collect());
+        return;
+    }
+    let first_kind = &topic.entries[positions[0]].kind;
+    let common_kind = positions
+        .iter()
+        .all(|&i| topic.entries[i].kind == *first_kind)
+        .then_some(first_kind);
+    let mut new_kind = None;
+    egui::ComboBox::new("bulk_kind_combo", "Kind")
+        .selected_text(common_kind.map_or("Mixed", EntryKind::label))
+        .show_ui(ui, |ui| {
+            for kind in [EntryKind::Task, EntryKind::Info] {
+                if ui
+                    .selectable_label(common_kind == Some(&kind), kind.label())
+                    .clicked()
+                {
+                    new_kind = Some(kind);
+                }
+            }
+        });
+    if let Some(kind) = new_kind {
+        for &i in &positions {
+            topic.entries[i].kind = kind.clone();
+        }
+    }
+}
+
 impl EntryKind {
     const fn label(&self) -> &'static str {
         match self {
@@ -308,8 +587,19 @@ impl EntryKind {
     }
 }
 
+impl DueStatus {
+    const fn color(self) -> egui::Color32 {
+        match self {
+            Self::Overdue => egui::Color32::LIGHT_RED,
+            Self::Today => egui::Color32::ORANGE,
+            Self::Soon => egui::Color32::YELLOW,
+        }
+    }
+}
+
 enum TaskUiCmd {
     GotoEntry { title: String },
+    GotoEntryId { id: Id },
 }
 
 fn entry_text_edit_id() -> egui::Id {
@@ -334,8 +624,37 @@ fn task_ui(
             // text editor focused.
             ui.checkbox(&mut app_temp.view_task_as_markdown, "Markdown [F2]")
                 .on_hover_text("View as markdown");
+            if ui
+                .button(ph::LINK)
+                .on_hover_text("Copy markdown link to this entry")
+                .clicked()
+            {
+                ui.ctx()
+                    .copy_text(format!("[{}](entry://id/{})", entry.title, entry.id));
+            }
         });
     });
+    ui.horizontal(|ui| {
+        let mut changed = false;
+        changed |= date_opt_ui(ui, "Due", "due_date", &mut entry.due);
+        ui.separator();
+        changed |= date_opt_ui(ui, "Scheduled", "scheduled_date", &mut entry.scheduled);
+        ui.separator();
+        changed |= recurrence_ui(ui, &mut entry.recurrence);
+        if changed {
+            app_temp.per_dirty = true;
+        }
+    });
+    if !entry.completion_log.is_empty() {
+        ui.collapsing(
+            format!("Completed {} times", entry.completion_log.len()),
+            |ui| {
+                for date in entry.completion_log.iter().rev() {
+                    ui.label(date.to_string());
+                }
+            },
+        );
+    }
     if ui.input(|inp| inp.key_pressed(egui::Key::F2)) {
         app_temp.view_task_as_markdown ^= true;
         if !app_temp.view_task_as_markdown {
@@ -354,9 +673,14 @@ fn task_ui(
                     if let egui::OutputCommand::OpenUrl(url) = cmd
                         && let Some(en_title) = url.url.strip_prefix("entry://")
                     {
-                        out_cmd = Some(TaskUiCmd::GotoEntry {
-                            title: en_title.to_owned(),
-                        });
+                        out_cmd = Some(
+                            match en_title.strip_prefix("id/").and_then(|id| id.parse().ok()) {
+                                Some(id) => TaskUiCmd::GotoEntryId { id },
+                                None => TaskUiCmd::GotoEntry {
+                                    title: en_title.to_owned(),
+                                },
+                            },
+                        );
                         retain = false;
                     }
                     retain
@@ -380,6 +704,93 @@ fn task_ui(
     out_cmd
 }
 
+/// Date picker for an optional date. Returns whether the date was changed.
+fn date_opt_ui(
+    ui: &mut egui::Ui,
+    label: &str,
+    id_salt: &str,
+    date: &mut Option<NaiveDate>,
+) -> bool {
+    ui.label(label);
+    match date {
+        Some(inner) => {
+            let mut changed = ui
+                .add(DatePickerButton::new(inner).id_salt(id_salt))
+                .changed();
+            if ui.button(ph::X).on_hover_text("Clear date").clicked() {
+                *date = None;
+                changed = true;
+            }
+            changed
+        }
+        None => {
+            if ui
+                .button(ph::CALENDAR_PLUS)
+                .on_hover_text("Set date")
+                .clicked()
+            {
+                *date = Some(chrono::Local::now().date_naive());
+                true
+            } else {
+                false
+            }
+        }
+    }
+}
+
+/// Editor for the recurrence rule of a task. Returns whether the rule was changed.
+fn recurrence_ui(ui: &mut egui::Ui, recurrence: &mut Option<Recurrence>) -> bool {
+    let mut changed = false;
+    egui::ComboBox::new("recurrence_combo", "")
+        .selected_text(recurrence_label(*recurrence))
+        .show_ui(ui, |ui| {
+            for choice in [
+                None,
+                Some(Recurrence::Days(7)),
+                Some(Recurrence::Weekdays),
+                Some(Recurrence::MonthDay(1)),
+            ] {
+                let label = recurrence_label(choice);
+                if ui
+                    .selectable_label(recurrence_label(*recurrence) == label, label)
+                    .clicked()
+                    && recurrence_label(*recurrence) != label
+                {
+                    *recurrence = choice;
+                    changed = true;
+                }
+            }
+        });
+    match recurrence {
+        Some(Recurrence::Days(n)) => {
+            changed |= ui
+                .add(
+                    egui::DragValue::new(n)
+                        .range(1..=365)
+                        .prefix("every ")
+                        .suffix(" days"),
+                )
+                .changed();
+        }
+        Some(Recurrence::MonthDay(day)) => {
+            changed |= ui
+                .add(egui::DragValue::new(day).range(1..=31).prefix("on day "))
+                .changed();
+        }
+        Some(Recurrence::Weekdays) | None => {}
+    }
+    changed
+}
+
+const fn recurrence_label(recurrence: Option<Recurrence>) -> &'static str {
+    match recurrence {
+        None => "Doesn't repeat",
+        Some(Recurrence::Days(_)) => "Every N days",
+        Some(Recurrence::Weekdays) => "Weekdays",
+        Some(Recurrence::MonthDay(_)) => "Monthly",
+    }
+}
+
 fn task_attachments_ui(entry: &mut Entry, app_temp: &mut TodoAppTemp, ui: &mut egui::Ui) {
     for attachment in &entry.attachments {
         ui.horizontal(|ui| {
@@ -433,7 +844,7 @@ fn task_attachments_ui(entry: &mut Entry, app_temp: &mut TodoAppTemp, ui: &mut e
                 let data = std::fs::read(&path).unwrap();
                 entry.attachments.push(Attachment {
                     filename: filename.into(),
-                    data,
+                    data: data.into(),
                 });
             } else {
                 error_msgbox(
@@ -0,0 +1,82 @@
+use {
+    crate::app::{ConfirmAction, TodoApp, UiState, backup_path, existing_backups},
+    eframe::egui,
+};
+
+pub fn ui(ui: &mut egui::Ui, app: &mut TodoApp) {
+    if ui.link("Back").clicked() {
+        app.temp.state = UiState::Normal;
+    }
+    ui.separator();
+    ui.heading("Preferences");
+    let settings = &mut app.per.settings;
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        ui.label("Backups to keep");
+        changed |= ui
+            .add(egui::DragValue::new(&mut settings.backup_count).range(0..=100))
+            .on_hover_text("At most one backup is made per hour")
+            .changed();
+    });
+    ui.horizontal(|ui| {
+        ui.label("Autosave every");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut settings.autosave_interval_secs)
+                    .range(0..=3600)
+                    .suffix(" s"),
+            )
+            .on_hover_text("0 disables autosave")
+            .changed();
+    });
+    changed |= ui
+        .checkbox(
+            &mut settings.save_on_focus_loss,
+            "Save when window loses focus",
+        )
+        .changed();
+    changed |= ui
+        .checkbox(
+            &mut settings.export_attachments,
+            "Include attachments when exporting",
+        )
+        .on_hover_text("They are written to a directory next to the exported file")
+        .changed();
+    changed |= ui
+        .checkbox(
+            &mut settings.auto_complete_parents,
+            "Mark tasks as done when all of their subtasks are",
+        )
+        .changed();
+    if changed {
+        app.temp.per_dirty = true;
+    }
+    ui.separator();
+    ui.heading("Backups");
+    let backups = existing_backups(&app.temp.data_file_path);
+    if backups.is_empty() {
+        ui.label("There are no backups yet. They are created when saving.");
+    }
+    egui_extras::TableBuilder::new(ui)
+        .column(egui_extras::Column::auto())
+        .column(egui_extras::Column::remainder())
+        .body(|mut body| {
+            for time in backups {
+                body.row(20.0, |mut row| {
+                    row.col(|ui| {
+                        ui.label(time.format("%Y-%m-%d %H:%M:%S").to_string())
+                            .on_hover_text(
+                                backup_path(&app.temp.data_file_path, time)
+                                    .display()
+                                    .to_string(),
+                            );
+                    });
+                    row.col(|ui| {
+                        if ui.button("Restore").clicked() {
+                            app.temp.confirm_action = Some(ConfirmAction::RestoreBackup(time));
+                        }
+                    });
+                });
+            }
+        });
+}
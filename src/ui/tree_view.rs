@@ -1,9 +1,14 @@
 use {
     crate::{
-        app::{ActionFlags, TodoApp, UiState, move_task_into_topic},
+        app::{
+            ActionFlags, DataFileOp, FileOp, ModalPayload, Placement, REDO_SHORTCUT, TodoApp,
+            UNDO_SHORTCUT, UiState,
+        },
         cmd::Cmd,
-        data::Topic,
-        tree,
+        data::{Id, Topic},
+        formats::{ExportFormat, ExportScope, ImportFormat},
+        ipc, tree,
+        ui::central_panel::DraggedEntry,
     },
     constcat::concat as cc,
     eframe::egui::{
@@ -45,6 +50,9 @@ pub fn ui(ui: &mut egui::Ui, app: &mut TodoApp) {
                     any_clicked = true;
                     app.per.topic_sel.clear();
                 }
+                if let Some(dragged) = re.dnd_release_payload::<DraggedTopic>() {
+                    app.per.move_topic_into(dragged.id, None);
+                }
                 ui.indent("root_indent", |ui| {
                     any_clicked |= topics_ui(
                         &mut app.per.topics,
@@ -65,7 +73,7 @@ pub fn ui(ui: &mut egui::Ui, app: &mut TodoApp) {
 
 fn tree_view_bottom_bar(ui: &mut egui::Ui, app: &mut TodoApp, any_clicked: bool) {
     ui.horizontal(|ui| match &mut app.temp.state {
-        UiState::AddSubtopic { name, parent_idx } => {
+        UiState::AddSubtopic { name, parent } => {
             let clicked = ui.button(ph::CHECK_FAT).clicked();
             if ui.button(ph::X_CIRCLE).clicked()
                 || ui.input(|inp| inp.key_pressed(egui::Key::Escape))
@@ -74,18 +82,15 @@ fn tree_view_bottom_bar(ui: &mut egui::Ui, app: &mut TodoApp, any_clicked: bool)
             } else {
                 ui.text_edit_singleline(name).request_focus();
                 if clicked || ui.input(|inp| inp.key_pressed(egui::Key::Enter)) {
-                    let topic_list = match tree::get_mut(&mut app.per.topics, parent_idx) {
+                    let parent_path = parent
+                        .and_then(|id| app.per.topic_path(id))
+                        .unwrap_or_default();
+                    let topic_list = match tree::get_mut(&mut app.per.topics, &parent_path) {
                         Some(topic) => &mut topic.children,
                         None => &mut app.per.topics,
                     };
-                    topic_list.push(Topic {
-                        name: name.take(),
-                        desc: String::new(),
-                        entries: Vec::new(),
-                        task_sel: None,
-                        children: Vec::new(),
-                    });
-                    let mut new_sel = parent_idx.clone();
+                    topic_list.push(Topic::new(name.take()));
+                    let mut new_sel = parent_path;
                     new_sel.push(topic_list.len() - 1);
                     app.temp.state = UiState::Normal;
                     // TODO: Do something more reasonable here
@@ -94,27 +99,43 @@ fn tree_view_bottom_bar(ui: &mut egui::Ui, app: &mut TodoApp, any_clicked: bool)
                 }
             }
         }
-        UiState::MoveTopicInto { src_idx } => {
-            ui.label("Click on topic to move into!");
+        UiState::MoveTopic { src, placement } => {
+            ui.label("Click on topic to move");
+            ui.selectable_value(placement, Placement::Before, "before");
+            ui.selectable_value(placement, Placement::After, "after");
+            ui.selectable_value(placement, Placement::Into, "into");
             if any_clicked {
-                tree::move_(&mut app.per.topics, src_idx, &app.per.topic_sel);
+                match app.per.selected_topic_id() {
+                    Some(dst) => app.per.move_topic(*src, dst, *placement),
+                    // The root can only be moved into
+                    None => app.per.move_topic_into(*src, None),
+                }
                 app.temp.state = UiState::Normal;
+                app.temp.per_dirty = true;
             }
             if ui.button("Cancel").clicked() {
                 app.temp.state = UiState::Normal;
             }
         }
-        UiState::MoveTaskIntoTopic(task) => {
-            if any_clicked {
-                let result = move_task_into_topic(
-                    &mut app.per.topics,
-                    std::mem::take(task),
-                    &app.per.topic_sel,
-                );
-                match result {
-                    Ok(()) => app.temp.state = UiState::Normal,
-                    Err(()) => eprintln!("Failed to move task into topic"),
+        UiState::MoveTaskIntoTopic(entries) => {
+            ui.label(format!(
+                "Click on topic to move {} {} into",
+                entries.len(),
+                if entries.len() == 1 {
+                    "entry"
+                } else {
+                    "entries"
+                }
+            ));
+            // Clicking the root keeps waiting for a topic
+            if any_clicked && let Some(dst) = app.per.selected_topic_id() {
+                for &entry in &*entries {
+                    app.per.move_entry_to_topic(entry, dst);
                 }
+                app.temp.state = UiState::Normal;
+                app.temp.per_dirty = true;
+            } else if ui.button("Cancel").clicked() {
+                app.temp.state = UiState::Normal;
             }
         }
         _ => bottom_bar_default_ui(app, ui),
@@ -128,17 +149,15 @@ fn bottom_bar_default_ui(app: &mut TodoApp, ui: &mut egui::Ui) {
             .on_hover_text("New topic")
             .clicked()
         {
-            app.temp.state = UiState::add_subtopic(app.per.topic_sel.clone());
+            app.temp.state = UiState::add_subtopic(app.per.selected_topic_id());
         }
         if ui
             .add_enabled(!app.per.topic_sel.is_empty(), egui::Button::new(ph::TRASH))
             .on_hover_text("Delete topic")
             .clicked()
-            && !app.per.topic_sel.is_empty()
+            && let Some(id) = app.per.selected_topic_id()
         {
-            tree::remove(&mut app.per.topics, &app.per.topic_sel);
-            // TODO: Do something more reasonable
-            app.per.topic_sel.clear();
+            app.temp.cmd.push(Cmd::RemoveTopic { id });
         }
         if let Some((last, first_chunk)) = app.per.topic_sel.split_last_mut() {
             let topics = if first_chunk.is_empty() {
@@ -170,10 +189,14 @@ fn bottom_bar_default_ui(app: &mut TodoApp, ui: &mut egui::Ui) {
             }
             if ui
                 .button("⬈ Move")
-                .on_hover_text("Move topic inside another topic")
+                .on_hover_text(
+                    "Move topic before, after or inside another topic\n\
+                     Topics can also be dragged around",
+                )
                 .clicked()
+                && let Some(id) = app.per.selected_topic_id()
             {
-                app.temp.state = UiState::move_topic_into(app.per.topic_sel.clone());
+                app.temp.state = UiState::move_topic(id);
             }
         }
     });
@@ -183,55 +206,7 @@ fn tree_view_top_bar(ui: &mut egui::Ui, app: &mut TodoApp) {
     let esc_pressed = ui.input(|inp| inp.key_pressed(egui::Key::Escape));
     ui.horizontal(|ui| {
         ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-            ui.menu_button("☰ Menu", |ui| {
-                if ui
-                    .add_enabled(
-                        app.temp.per_dirty,
-                        egui::Button::new("💾 Save").shortcut_text("Ctrl+S"),
-                    )
-                    .clicked()
-                    && let Err(e) = app.save_persistent()
-                {
-                    eprintln!("Error when saving: {e}");
-                }
-                if ui
-                    .add_enabled(
-                        app.temp.per_dirty,
-                        egui::Button::new("⟲ Reload").shortcut_text("Ctrl+R"),
-                    )
-                    .clicked()
-                    && let Err(e) = app.reload_persistent()
-                {
-                    eprintln!("Reload error: {e}");
-                }
-                ui.separator();
-                if ui
-                    .button(cc!(ph::ARROW_BEND_LEFT_UP, " Collapse all"))
-                    .clicked()
-                {
-                    app.temp.action_flags.collapse_all = true;
-                }
-                if ui
-                    .button(cc!(ph::ARROW_BEND_RIGHT_DOWN, " Expand all"))
-                    .clicked()
-                {
-                    app.temp.action_flags.expand_all = true;
-                }
-                ui.separator();
-                if ui.button("🗛 Font config").clicked() {
-                    app.temp.state = UiState::FontCfg;
-                }
-                ui.separator();
-                if ui
-                    .add(
-                        egui::Button::new(cc!(ph::DOOR_OPEN, " Save & Quit"))
-                            .shortcut_text("Ctrl+Q"),
-                    )
-                    .clicked()
-                {
-                    ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
-                }
-            });
+            ui.menu_button("☰ Menu", |ui| menu_ui(ui, app));
             if ui
                 .button("👁 Hide")
                 .on_hover_text("Hotkey: Esc\nAlso autosaves.")
@@ -257,6 +232,191 @@ fn tree_view_top_bar(ui: &mut egui::Ui, app: &mut TodoApp) {
     });
 }
 
+fn menu_ui(ui: &mut egui::Ui, app: &mut TodoApp) {
+    ui.menu_button(cc!(ph::FOLDER, " File"), |ui| file_menu_ui(ui, app));
+    ui.separator();
+    if ui
+        .add_enabled(
+            app.temp.per_dirty,
+            egui::Button::new("💾 Save").shortcut_text("Ctrl+S"),
+        )
+        .clicked()
+        && let Err(e) = app.save_persistent()
+    {
+        eprintln!("Error when saving: {e}");
+    }
+    if ui
+        .add_enabled(
+            app.temp.per_dirty,
+            egui::Button::new("⟲ Reload").shortcut_text("Ctrl+R"),
+        )
+        .clicked()
+        && let Err(e) = app.reload_persistent()
+    {
+        eprintln!("Reload error: {e}");
+    }
+    ui.separator();
+    if ui
+        .add_enabled(
+            app.temp.history.can_undo(),
+            egui::Button::new(cc!(ph::ARROW_COUNTER_CLOCKWISE, " Undo"))
+                .shortcut_text(ui.ctx().format_shortcut(&UNDO_SHORTCUT)),
+        )
+        .clicked()
+    {
+        app.temp.cmd.push(Cmd::Undo);
+    }
+    if ui
+        .add_enabled(
+            app.temp.history.can_redo(),
+            egui::Button::new(cc!(ph::ARROW_CLOCKWISE, " Redo"))
+                .shortcut_text(ui.ctx().format_shortcut(&REDO_SHORTCUT)),
+        )
+        .clicked()
+    {
+        app.temp.cmd.push(Cmd::Redo);
+    }
+    ui.separator();
+    if ui
+        .button(cc!(ph::ARROW_BEND_LEFT_UP, " Collapse all"))
+        .clicked()
+    {
+        app.temp.action_flags.collapse_all = true;
+    }
+    if ui
+        .button(cc!(ph::ARROW_BEND_RIGHT_DOWN, " Expand all"))
+        .clicked()
+    {
+        app.temp.action_flags.expand_all = true;
+    }
+    ui.separator();
+    if ui.button(cc!(ph::CALENDAR, " Agenda")).clicked() {
+        app.temp.state = UiState::Agenda;
+    }
+    if ui.button("🗛 Font config").clicked() {
+        app.temp.state = UiState::FontCfg;
+    }
+    if ui.button(cc!(ph::GEAR, " Preferences")).clicked() {
+        app.temp.state = UiState::Preferences;
+    }
+    ui.separator();
+    if ui
+        .add(egui::Button::new(cc!(ph::DOOR_OPEN, " Save & Quit")).shortcut_text("Ctrl+Q"))
+        .clicked()
+    {
+        ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
+    }
+}
+
+fn file_menu_ui(ui: &mut egui::Ui, app: &mut TodoApp) {
+    data_file_buttons_ui(ui, app);
+    ui.menu_button(cc!(ph::EXPORT, " Export"), |ui| {
+        for format in ExportFormat::ALL {
+            ui.menu_button(format.label(), |ui| {
+                let has_sel = tree::get(&app.per.topics, &app.per.topic_sel).is_some();
+                let scopes = [
+                    (ExportScope::SelectedTopic, "Selected topic...", has_sel),
+                    (ExportScope::All, "All topics...", true),
+                ];
+                for (scope, label, enabled) in scopes {
+                    if ui.add_enabled(enabled, egui::Button::new(label)).clicked() {
+                        app.temp.file_op = Some(FileOp::Export { format, scope });
+                        app.temp.file_dialog.save_file();
+                    }
+                }
+            });
+        }
+    });
+    ui.menu_button(cc!(ph::DOWNLOAD_SIMPLE, " Import"), |ui| {
+        for format in ImportFormat::ALL {
+            if ui
+                .button(format!("{}...", format.label()))
+                .on_hover_text("Into the selected topic")
+                .clicked()
+            {
+                app.temp.file_op = Some(FileOp::Import(format));
+                app.temp.file_dialog.pick_file();
+            }
+        }
+    });
+    ui.separator();
+    if ui
+        .button(cc!(ph::FILE_TEXT, " Dump as text..."))
+        .on_hover_text("Everything in a readable and editable form (RON)")
+        .clicked()
+    {
+        app.temp.file_op = Some(FileOp::DumpText);
+        app.temp.file_dialog.save_file();
+    }
+    if ui
+        .button(cc!(ph::FILE_ARROW_UP, " Load text dump..."))
+        .on_hover_text("Replace everything with the contents of a text dump")
+        .clicked()
+    {
+        app.temp.file_op = Some(FileOp::LoadText);
+        app.temp.file_dialog.pick_file();
+    }
+    ui.separator();
+    ui.label("Recent files");
+    let current = ipc::canonical_path(&app.temp.data_file_path);
+    let mut switch_to = None;
+    for path in &app.temp.recent_files {
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        if ui
+            .add_enabled(*path != current, egui::Button::new(name))
+            .on_hover_text(path.display().to_string())
+            .clicked()
+        {
+            switch_to = Some(path.clone());
+        }
+    }
+    if let Some(path) = switch_to
+        && let Err(e) = app.switch_data_file(ui.ctx(), DataFileOp::Open, path)
+    {
+        // Saving the current file may have already asked about something
+        app.temp
+            .modal
+            .get_or_insert(ModalPayload::ErrorMsg(e.to_string()));
+    }
+}
+
+fn data_file_buttons_ui(ui: &mut egui::Ui, app: &mut TodoApp) {
+    if ui.button(cc!(ph::FOLDER_OPEN, " Open...")).clicked() {
+        app.temp.file_op = Some(FileOp::DataFile(DataFileOp::Open));
+        app.temp.file_dialog.pick_file();
+    }
+    if ui
+        .button(cc!(ph::FOLDER_OPEN, " Open directory store..."))
+        .on_hover_text("A directory with a file per entry, for version control")
+        .clicked()
+    {
+        app.temp.file_op = Some(FileOp::DataFile(DataFileOp::Open));
+        app.temp.file_dialog.pick_directory();
+    }
+    if ui.button(cc!(ph::FILE_PLUS, " New...")).clicked() {
+        app.temp.file_op = Some(FileOp::DataFile(DataFileOp::New));
+        app.temp.file_dialog.save_file();
+    }
+    if ui.button(cc!(ph::FLOPPY_DISK, " Save as...")).clicked() {
+        app.temp.file_op = Some(FileOp::DataFile(DataFileOp::SaveAs));
+        app.temp.file_dialog.save_file();
+    }
+    if ui
+        .button(cc!(ph::FOLDERS, " Save as directory store..."))
+        .on_hover_text(
+            "A directory with a file per entry, which can be kept in git.\n\
+             Files of setodo in the chosen directory are replaced.",
+        )
+        .clicked()
+    {
+        app.temp.file_op = Some(FileOp::DataFile(DataFileOp::SaveAsDirectory));
+        app.temp.file_dialog.pick_directory();
+    }
+}
+
 fn find_ui(ui: &mut egui::Ui, app: &mut TodoApp) {
     let find_str = &app.temp.find_string;
     for matched_topic in collect_matches(&app.per.topics, find_str) {
@@ -272,7 +432,7 @@ fn find_ui(ui: &mut egui::Ui, app: &mut TodoApp) {
             for en_idx in matched_topic.matching_entries {
                 if ui.link(&topic.entries[en_idx].title).clicked() {
                     app.per.topic_sel = matched_topic.cursor;
-                    topic.task_sel = Some(en_idx);
+                    topic.select_entry(en_idx);
                     return;
                 }
             }
@@ -296,7 +456,7 @@ fn topics_ui(
     for (i, topic) in topics.iter_mut().enumerate() {
         *cursor.last_mut().unwrap() = i;
         match state {
-            UiState::RenameTopic { idx } if idx == cursor => {
+            UiState::RenameTopic { id } if *id == topic.id => {
                 rename_topic_ui(ui, state, per_dirty, cmd, topic);
             }
             _ => {
@@ -309,9 +469,7 @@ fn topics_ui(
                     topic: &mut Topic,
                 ) {
                     if ui.button(cc!(ph::NOTE_PENCIL, " Rename topic")).clicked() {
-                        *state = UiState::RenameTopic {
-                            idx: cursor.to_owned(),
-                        };
+                        *state = UiState::RenameTopic { id: topic.id };
                         cmd.push(Cmd::FocusTextEdit);
                     }
                     if ui.button(cc!(ph::FILE_PLUS, " Create subtopic")).clicked() {
@@ -319,26 +477,25 @@ fn topics_ui(
                         *topic_sel = [cursor, &[topic.children.len() - 1]].concat();
                     }
                     if ui.button(cc!(ph::TRASH, " Delete topic")).clicked() {
-                        cmd.push(Cmd::RemoveTopic {
-                            idx: cursor.to_owned(),
-                        });
+                        cmd.push(Cmd::RemoveTopic { id: topic.id });
                     }
                 }
                 if topic.children.is_empty() {
-                    let re = ui.selectable_label(*topic_sel == *cursor, &topic.name);
+                    let re = ui
+                        .selectable_label(*topic_sel == *cursor, &topic.name)
+                        .interact(egui::Sense::drag());
+                    topic_drag_and_drop(ui, &re, topic.id, cursor, cmd);
                     if re.clicked() {
                         any_clicked = true;
                         topic_sel.clone_from(cursor);
                     }
                     if re.double_clicked() {
-                        *state = UiState::RenameTopic {
-                            idx: cursor.clone(),
-                        };
+                        *state = UiState::RenameTopic { id: topic.id };
                         cmd.push(Cmd::FocusTextEdit);
                     }
                     re.context_menu(|ui| ctx_menu(ui, state, cursor, topic_sel, cmd, topic));
                 } else {
-                    let id = ui.make_persistent_id("cheader").with(&topic.name);
+                    let id = ui.make_persistent_id("cheader").with(topic.id);
                     let mut cs = CollapsingState::load_with_default_open(ui.ctx(), id, false);
                     if action_flags.collapse_all {
                         cs.set_open(false);
@@ -347,15 +504,16 @@ fn topics_ui(
                         cs.set_open(true);
                     }
                     cs.show_header(ui, |ui| {
-                        let re = ui.selectable_label(*topic_sel == *cursor, &topic.name);
+                        let re = ui
+                            .selectable_label(*topic_sel == *cursor, &topic.name)
+                            .interact(egui::Sense::drag());
+                        topic_drag_and_drop(ui, &re, topic.id, cursor, cmd);
                         if re.clicked() {
                             topic_sel.clone_from(cursor);
                             any_clicked = true;
                         }
                         if re.double_clicked() {
-                            *state = UiState::RenameTopic {
-                                idx: cursor.clone(),
-                            }
+                            *state = UiState::RenameTopic { id: topic.id }
                         }
                         re.context_menu(|ui| ctx_menu(ui, state, cursor, topic_sel, cmd, topic));
                     })
@@ -379,6 +537,83 @@ fn topics_ui(
     any_clicked
 }
 
+/// Payload of a topic being dragged
+struct DraggedTopic {
+    id: Id,
+    path: Vec<usize>,
+}
+
+/// Make the label of a topic draggable, and let other topics be dropped before, after or
+/// into it. Entries can be dropped into it too.
+fn topic_drag_and_drop(
+    ui: &egui::Ui,
+    re: &egui::Response,
+    id: Id,
+    cursor: &[usize],
+    cmd: &mut Vec<Cmd>,
+) {
+    re.dnd_set_drag_payload(DraggedTopic {
+        id,
+        path: cursor.to_vec(),
+    });
+    if re.dnd_hover_payload::<DraggedEntry>().is_some() {
+        let stroke = ui.visuals().selection.stroke;
+        ui.painter()
+            .rect_stroke(re.rect, 2.0, stroke, egui::StrokeKind::Inside);
+        if let Some(dragged) = re.dnd_release_payload::<DraggedEntry>() {
+            cmd.push(Cmd::MoveEntryToTopic {
+                entry: dragged.id,
+                topic: id,
+            });
+        }
+        return;
+    }
+    let Some(dragged) = re.dnd_hover_payload::<DraggedTopic>() else {
+        return;
+    };
+    // A topic can't go into itself, or its own children
+    if !tree::move_precondition(&dragged.path, cursor) {
+        return;
+    }
+    let Some(pointer_pos) = ui.ctx().pointer_latest_pos() else {
+        return;
+    };
+    let placement = drop_placement(re.rect, pointer_pos.y);
+    let stroke = ui.visuals().selection.stroke;
+    let painter = ui.painter();
+    match placement {
+        Placement::Before => {
+            painter.hline(re.rect.x_range(), re.rect.top(), stroke);
+        }
+        Placement::After => {
+            painter.hline(re.rect.x_range(), re.rect.bottom(), stroke);
+        }
+        Placement::Into => {
+            painter.rect_stroke(re.rect, 2.0, stroke, egui::StrokeKind::Inside);
+        }
+    }
+    if let Some(dragged) = re.dnd_release_payload::<DraggedTopic>() {
+        cmd.push(Cmd::MoveTopic {
+            src: dragged.id,
+            dst: id,
+            placement,
+        });
+    }
+}
+
+/// Dropping on the top or bottom quarter of a label puts the topic next to it, the rest
+/// puts it inside
+fn drop_placement(rect: egui::Rect, y: f32) -> Placement {
+    let quarter = rect.height() / 4.0;
+    if y < rect.top() + quarter {
+        Placement::Before
+    } else if y > rect.bottom() - quarter {
+        Placement::After
+    } else {
+        Placement::Into
+    }
+}
+
 fn rename_topic_ui(
     ui: &mut egui::Ui,
     state: &mut UiState,